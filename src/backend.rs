@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use aws_sdk_sqs::types::{Message, MessageAttributeValue, QueueAttributeName};
+
+/// SQS won't accept message bodies bigger than 256kb
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+/// SQS won't handle more than 10 messages in a single request
+pub const MAX_BATCH_SIZE: usize = 10;
+
+/// SQS won't accept messages with more than 10 attributes
+pub const MAX_MESSAGE_ATTRIBUTES: usize = 10;
+
+/// SQS won't delay messages for longer than 15 minutes
+pub const MAX_DELAY_SECONDS: i32 = 900;
+
+/// Whether SQS takes `body` as a message body: it only accepts the characters allowed in XML,
+/// which leaves out most control characters
+pub fn is_valid_body(body: &str) -> bool {
+    body.chars().all(|c| matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..))
+}
+
+/// Message about to be sent to a queue
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingMessage {
+    pub body: String,
+    pub attributes: HashMap<String, MessageAttributeValue>,
+    /// FIFO queues only
+    pub group_id: Option<String>,
+    /// FIFO queues only, required unless the queue has content-based deduplication enabled
+    pub deduplication_id: Option<String>,
+    /// How long the message stays hidden once sent, up to `MAX_DELAY_SECONDS`. Standard queues
+    /// only, FIFO queues can only delay all messages
+    pub delay_seconds: Option<i32>,
+}
+
+impl OutgoingMessage {
+    pub fn new(body: String) -> Self {
+        OutgoingMessage {
+            body,
+            ..Default::default()
+        }
+    }
+
+    /// Size of the message as far as SQS limits are concerned, which includes the name, type
+    /// and value of each attribute
+    pub fn size(&self) -> usize {
+        let attributes_size: usize = self.attributes.iter()
+            .map(|(name, value)| {
+                name.len()
+                    + value.data_type().len()
+                    + value.string_value().map_or(0, str::len)
+                    + value.binary_value().map_or(0, |value| value.as_ref().len())
+            })
+            .sum();
+
+        self.body.len() + attributes_size
+    }
+}
+
+/// Message of a batch that SQS refused
+pub struct BatchEntryFailure {
+    /// Position of the message within the batch
+    pub index: usize,
+    /// Whether the message itself is to blame, in which case sending it again won't help
+    pub sender_fault: bool,
+    pub error: anyhow::Error,
+}
+
+/// Queue operations needed by sqsfs. `SQSClient` talks to Amazon SQS while `InMemoryBackend`
+/// keeps everything within the mount process, which is handy for tests and demos.
+pub trait QueueBackend: Send + Sync {
+    /// List the URLs of all queues visible to this backend
+    fn list_queues(&self) -> anyhow::Result<Vec<String>>;
+
+    /// Create a queue, returning its URL. Queues named like FIFO queues, i.e. with a .fifo suffix,
+    /// are created as such
+    fn create_queue(&self, queue_name: &str) -> anyhow::Result<String>;
+
+    /// Delete the queue along with its messages
+    fn delete_queue(&self, queue_url: &str) -> anyhow::Result<()>;
+
+    /// Fetch all attributes of the queue, e.g. whether it has content-based deduplication enabled
+    fn get_queue_attributes(&self, queue_url: &str) -> anyhow::Result<HashMap<QueueAttributeName, String>>;
+
+    /// Send a message to the queue, returning the number of bytes sent
+    fn send_message(&self, queue_url: &str, message: &OutgoingMessage) -> anyhow::Result<u32>;
+
+    /// Send up to `MAX_BATCH_SIZE` messages, at most `MAX_MESSAGE_SIZE` altogether, in a single
+    /// request. Some messages may be refused while the other ones go through, so the messages that
+    /// weren't sent are returned rather than failing the whole call
+    fn send_message_batch(&self, queue_url: &str, messages: &[OutgoingMessage]) -> anyhow::Result<Vec<BatchEntryFailure>>;
+
+    /// Receive at most `max_messages` messages (up to `MAX_BATCH_SIZE`) from the queue, waiting up
+    /// to `wait_time_secs` for one to arrive. An empty vector means the queue had nothing to offer
+    /// within that time
+    fn receive_message(&self, queue_url: &str, max_messages: i32, wait_time_secs: i32) -> anyhow::Result<Vec<Message>>;
+
+    /// Delete a previously received message using its receipt handle
+    fn delete_message(&self, queue_url: &str, receipt_handle: &str) -> anyhow::Result<()>;
+
+    /// Delete up to `MAX_BATCH_SIZE` previously received messages in a single request. Messages
+    /// that can't be deleted don't prevent the other ones from being deleted, but make the whole
+    /// call fail
+    fn delete_message_batch(&self, queue_url: &str, receipt_handles: &[String]) -> anyhow::Result<()>;
+
+    /// Change for how long a received message stays invisible to other consumers, counting from
+    /// now. A timeout of zero makes it available again straight away
+    fn change_message_visibility(&self, queue_url: &str, receipt_handle: &str, visibility_timeout: i32) -> anyhow::Result<()>;
+}
@@ -1,12 +1,16 @@
 use std::path::PathBuf;
 
 use anyhow::anyhow;
-use clap::{ArgAction, crate_name, crate_version, Parser};
+use clap::{ArgAction, crate_name, crate_version, Parser, ValueEnum};
 use fuser::MountOption;
 use log::debug;
 use procfs::process::Process;
+use url::Url;
 
+use crate::backend::{MAX_DELAY_SECONDS, QueueBackend};
 use crate::fuse::SQSFuse;
+use crate::memory::InMemoryBackend;
+use crate::sqs::SQSClient;
 
 const MOUNT_OPTIONS_HEADER: &str = "Mount options";
 const SQS_OPTIONS_HEADER: &str = "SQS options";
@@ -28,6 +32,23 @@ pub struct CliArgs {
     )]
     auto_unmount: bool,
 
+    #[arg(
+    long,
+    help = "How queues show up in the mount point",
+    value_enum,
+    default_value_t = Layout::Flat,
+    help_heading = MOUNT_OPTIONS_HEADER,
+    )]
+    pub layout: Layout,
+
+    #[arg(
+    long,
+    help = "Allow queues to be created (touch, mkdir) and deleted (rm, rmdir) through the mount point",
+    action = ArgAction::SetTrue,
+    help_heading = MOUNT_OPTIONS_HEADER,
+    )]
+    pub allow_queue_admin: bool,
+
     #[arg(
     short,
     long,
@@ -36,9 +57,316 @@ pub struct CliArgs {
     help_heading = SQS_OPTIONS_HEADER,
     )]
     pub cache_ttl_in_secs: u64,
+
+    #[arg(
+    long,
+    help = "When messages read from a queue get deleted",
+    value_enum,
+    default_value_t = ReadMode::Consume,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub read_mode: ReadMode,
+
+    #[arg(
+    long,
+    help = "How long a read waits for a message to arrive when the queue is empty (long polling)",
+    default_value = "0",
+    value_parser = clap::value_parser!(i32).range(0..=20),
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub wait_time_secs: i32,
+
+    #[arg(
+    long,
+    help = "Keep polling until a message arrives (or the reader is interrupted) instead of returning an empty file",
+    action = ArgAction::SetTrue,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub poll_until_message: bool,
+
+    #[arg(
+    long,
+    help = "Turn queues into endless streams of newline-terminated messages, to be read sequentially, e.g. with cat",
+    action = ArgAction::SetTrue,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub stream: bool,
+
+    #[arg(
+    long,
+    help = "How many messages to receive at once, all of them being returned by the same read",
+    default_value = "1",
+    value_parser = clap::value_parser!(i32).range(1..=10),
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub batch_size: i32,
+
+    #[arg(
+    long,
+    help = "How messages are laid out when several of them are read at once",
+    value_enum,
+    default_value_t = Delimiter::Newline,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub delimiter: Delimiter,
+
+    #[arg(
+    long,
+    help = "What a file written to a queue turns into",
+    value_enum,
+    default_value_t = WriteMode::Message,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub write_mode: WriteMode,
+
+    #[arg(
+    long,
+    help = "Base64-encode data SQS won't take as text (not UTF-8, or with control characters) when writing, and decode it when reading",
+    action = ArgAction::SetTrue,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub binary: bool,
+
+    #[arg(
+    long,
+    help = "Message group ID of messages written to FIFO queues, unless set through the user.sqs.group_id xattr",
+    value_name = "GROUP_ID",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub message_group_id: Option<String>,
+
+    #[arg(
+    long,
+    help = "Delay of messages written to standard queues, unless set through the user.sqs.delay_seconds xattr",
+    value_parser = clap::value_parser!(i32).range(0..=MAX_DELAY_SECONDS as i64),
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub delay_seconds: Option<i32>,
+
+    #[arg(
+    long,
+    help = "Keep messages written to scheduled/<QUEUE>/<RFC 3339 time> in this directory and send them once due",
+    value_name = "DIR",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub spool_dir: Option<PathBuf>,
+
+    #[arg(
+    long = "queue-option",
+    help = "Override a setting for a single queue, e.g. jobs:wait_time_secs=20 (can be repeated)",
+    value_name = "QUEUE:KEY=VALUE",
+    value_parser = parse_queue_option,
+    action = ArgAction::Append,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub queue_options: Vec<QueueOption>,
+
+    #[arg(
+    long,
+    help = "Serve the given comma-separated queues from memory instead of connecting to SQS",
+    value_name = "QUEUE_NAMES",
+    value_delimiter = ',',
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub in_memory_queues: Vec<String>,
+
+    #[arg(
+    long,
+    help = "Custom SQS endpoint, e.g. ElasticMQ/LocalStack or a VPC interface endpoint",
+    env = "AWS_ENDPOINT_URL_SQS",
+    hide_env_values = true,
+    value_parser = parse_endpoint_url,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub endpoint_url: Option<String>,
+
+    #[arg(
+    long,
+    help = "AWS region of the SQS queues [default: resolved from the environment/profile]",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub region: Option<String>,
+
+    #[arg(
+    long,
+    help = "Named profile from the AWS config/credentials files",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+    long,
+    help = "ARN of a role to assume before accessing SQS, e.g. for queues owned by another account",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub role_arn: Option<String>,
+
+    #[arg(
+    long,
+    help = "Session name used when assuming --role-arn",
+    default_value = "mountpoint-sqs",
+    requires = "role_arn",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub role_session_name: String,
+
+    #[arg(
+    long,
+    help = "External ID required by the trust policy of --role-arn",
+    requires = "role_arn",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub external_id: Option<String>,
+
+    #[arg(
+    long,
+    help = "Maximum number of attempts for each SQS request, including the first one",
+    default_value = "3",
+    value_parser = clap::value_parser!(u32).range(1..),
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub max_attempts: u32,
+
+    #[arg(
+    long,
+    help = "How to back off between attempts",
+    value_enum,
+    default_value_t = BackoffMode::Standard,
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub backoff_mode: BackoffMode,
+
+    #[arg(
+    long,
+    help = "How long to wait for a connection to SQS to be established [default: SDK default]",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub connect_timeout_in_secs: Option<u64>,
+
+    #[arg(
+    long,
+    help = "How long an SQS operation may take, including retries [default: no limit]",
+    help_heading = SQS_OPTIONS_HEADER,
+    )]
+    pub operation_timeout_in_secs: Option<u64>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Each queue is a file, read and written according to the read and write modes
+    Flat,
+    /// Each queue is a directory, holding files to read messages (messages), peek at them
+    /// (peek), send them (send), receive them until deleted (receive and inflight/), and to look
+    /// at the queue (stats, attributes/ and dlq)
+    Directories,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Delete messages as soon as they are received, before handing them to the reader
+    Consume,
+    /// Delete messages once the reader closes the file after reading them to the end (or calls
+    /// fsync), otherwise they reappear after their visibility timeout
+    AtLeastOnce,
+    /// Never delete messages, they are made visible again as soon as they are received
+    Peek,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Everything written until the file is closed is sent as a single message
+    Message,
+    /// Each line (or NUL-separated record with --delimiter nul) is sent as a message of its own,
+    /// in batches of up to 10
+    Lines,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    /// One message per line
+    Newline,
+    /// Messages separated by a NUL byte, e.g. for xargs -0
+    Nul,
+    /// One JSON object per line holding the message ID and body
+    JsonLines,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffMode {
+    /// Exponential backoff with jitter
+    Standard,
+    /// Standard backoff plus client-side rate limiting when SQS throttles requests
+    Adaptive,
+}
+
+/// Settings that can be overridden for a single queue through `--queue-option`
+#[derive(Debug, Clone)]
+pub enum QueueSetting {
+    WaitTimeSecs(i32),
+    PollUntilMessage(bool),
+    Stream(bool),
+    BatchSize(i32),
+    Delimiter(Delimiter),
+    WriteMode(WriteMode),
+    Binary(bool),
+    MessageGroupId(String),
+    DelaySeconds(i32),
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueOption {
+    pub queue_name: String,
+    pub setting: QueueSetting,
+}
+
+/// Effective settings of a queue, i.e. mount-wide settings plus the queue's own overrides
+#[derive(Debug, Clone)]
+pub struct QueueSettings {
+    pub wait_time_secs: i32,
+    pub poll_until_message: bool,
+    pub stream: bool,
+    pub batch_size: i32,
+    pub delimiter: Delimiter,
+    pub write_mode: WriteMode,
+    pub binary: bool,
+    pub message_group_id: Option<String>,
+    pub delay_seconds: Option<i32>,
 }
 
 impl CliArgs {
+    pub fn queue_settings(&self, queue_name: &str) -> QueueSettings {
+        let mut settings = QueueSettings {
+            wait_time_secs: self.wait_time_secs,
+            poll_until_message: self.poll_until_message,
+            stream: self.stream,
+            batch_size: self.batch_size,
+            delimiter: self.delimiter,
+            write_mode: self.write_mode,
+            binary: self.binary,
+            message_group_id: self.message_group_id.clone(),
+            delay_seconds: self.delay_seconds,
+        };
+
+        let overrides = self.queue_options.iter()
+            .filter(|option| option.queue_name == queue_name);
+
+        for option in overrides {
+            match option.setting.clone() {
+                QueueSetting::WaitTimeSecs(wait_time_secs) => settings.wait_time_secs = wait_time_secs,
+                QueueSetting::PollUntilMessage(poll) => settings.poll_until_message = poll,
+                QueueSetting::Stream(stream) => settings.stream = stream,
+                QueueSetting::BatchSize(batch_size) => settings.batch_size = batch_size,
+                QueueSetting::Delimiter(delimiter) => settings.delimiter = delimiter,
+                QueueSetting::WriteMode(write_mode) => settings.write_mode = write_mode,
+                QueueSetting::Binary(binary) => settings.binary = binary,
+                QueueSetting::MessageGroupId(group_id) => settings.message_group_id = Some(group_id),
+                QueueSetting::DelaySeconds(delay_seconds) => settings.delay_seconds = Some(delay_seconds),
+            }
+        }
+
+        settings
+    }
+
     fn build_options(&self) -> Vec<MountOption> {
         let mut options = vec![
             MountOption::RW,
@@ -49,8 +377,66 @@ impl CliArgs {
         }
         options
     }
+
+    fn build_backend(&self) -> anyhow::Result<Box<dyn QueueBackend>> {
+        if self.in_memory_queues.is_empty() {
+            Ok(Box::new(SQSClient::new(self)?))
+        } else {
+            Ok(Box::new(InMemoryBackend::new(&self.in_memory_queues)))
+        }
+    }
 }
 
+fn parse_endpoint_url(value: &str) -> anyhow::Result<String> {
+    let url = Url::parse(value)?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(anyhow!("endpoint url must use either http or https: {}", value));
+    }
+    Ok(value.to_string())
+}
+
+fn parse_queue_option(value: &str) -> anyhow::Result<QueueOption> {
+    let (queue_name, setting) = value.split_once(':')
+        .ok_or_else(|| anyhow!("expected QUEUE:KEY=VALUE, got: {}", value))?;
+    let (key, setting_value) = setting.split_once('=')
+        .ok_or_else(|| anyhow!("expected QUEUE:KEY=VALUE, got: {}", value))?;
+
+    let setting = match key {
+        "wait_time_secs" => {
+            let wait_time_secs: i32 = setting_value.parse()?;
+            if !(0..=20).contains(&wait_time_secs) {
+                return Err(anyhow!("wait_time_secs must be between 0 and 20, got: {}", wait_time_secs));
+            }
+            QueueSetting::WaitTimeSecs(wait_time_secs)
+        }
+        "poll_until_message" => QueueSetting::PollUntilMessage(setting_value.parse()?),
+        "stream" => QueueSetting::Stream(setting_value.parse()?),
+        "batch_size" => {
+            let batch_size: i32 = setting_value.parse()?;
+            if !(1..=10).contains(&batch_size) {
+                return Err(anyhow!("batch_size must be between 1 and 10, got: {}", batch_size));
+            }
+            QueueSetting::BatchSize(batch_size)
+        }
+        "delimiter" => QueueSetting::Delimiter(Delimiter::from_str(setting_value, true).map_err(|error| anyhow!(error))?),
+        "write_mode" => QueueSetting::WriteMode(WriteMode::from_str(setting_value, true).map_err(|error| anyhow!(error))?),
+        "binary" => QueueSetting::Binary(setting_value.parse()?),
+        "message_group_id" => QueueSetting::MessageGroupId(setting_value.to_string()),
+        "delay_seconds" => {
+            let delay_seconds: i32 = setting_value.parse()?;
+            if !(0..=MAX_DELAY_SECONDS).contains(&delay_seconds) {
+                return Err(anyhow!("delay_seconds must be between 0 and {}, got: {}", MAX_DELAY_SECONDS, delay_seconds));
+            }
+            QueueSetting::DelaySeconds(delay_seconds)
+        }
+        _ => return Err(anyhow!("unknown queue option: {}", key)),
+    };
+
+    Ok(QueueOption {
+        queue_name: queue_name.to_string(),
+        setting,
+    })
+}
 
 // Credits: https://github.com/awslabs/mountpoint-s3/blob/9d22f1f77f232baba714e5775bdef171d77e71d9/mountpoint-s3/src/cli.rs#L939-L970
 fn validate_mountpoint(path: &PathBuf) -> anyhow::Result<()> {
@@ -93,10 +479,67 @@ pub fn main() {
     validate_mountpoint(&args.mount_point).expect("Failure when validating mount point");
 
     // mount sqsfs
-    let fuse_fs = SQSFuse::new(args.clone());
+    let backend = args.build_backend().expect("Failure when configuring the SQS client");
+    let fuse_fs = SQSFuse::new(args.clone(), backend).expect("Failure when starting sqsfs");
     fuser::mount2(
         fuse_fs,
         args.mount_point,
         &options,
     ).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_urls_must_use_http() {
+        assert!(parse_endpoint_url("http://localhost:9324").is_ok());
+        assert!(parse_endpoint_url("https://sqs.us-east-1.amazonaws.com").is_ok());
+        assert!(parse_endpoint_url("ftp://localhost:9324").is_err());
+        assert!(parse_endpoint_url("localhost:9324").is_err());
+    }
+
+    #[test]
+    fn retries_need_at_least_one_attempt() {
+        let parse = |args: &[&str]| CliArgs::try_parse_from(["mountpoint-sqs", "/mnt"].iter().chain(args));
+
+        let cli_args = parse(&["--max-attempts", "5", "--backoff-mode", "adaptive"]).unwrap();
+        assert_eq!(cli_args.max_attempts, 5);
+        assert_eq!(cli_args.backoff_mode, BackoffMode::Adaptive);
+        assert!(parse(&["--max-attempts", "0"]).is_err());
+    }
+
+    #[test]
+    fn queue_options_override_mount_settings() {
+        let cli_args = CliArgs::parse_from(["mountpoint-sqs", "/mnt", "--wait-time-secs", "5", "--queue-option", "jobs:wait_time_secs=20", "--queue-option", "jobs:poll_until_message=true"]);
+
+        let settings = cli_args.queue_settings("jobs");
+        assert_eq!(settings.wait_time_secs, 20);
+        assert!(settings.poll_until_message);
+        let settings = cli_args.queue_settings("events");
+        assert_eq!(settings.wait_time_secs, 5);
+        assert!(!settings.poll_until_message);
+    }
+
+    #[test]
+    fn queue_options_are_range_checked() {
+        assert!(parse_queue_option("jobs:wait_time_secs=20").is_ok());
+        assert!(parse_queue_option("jobs:wait_time_secs=21").is_err());
+        assert!(parse_queue_option("jobs:wait_time_secs=-1").is_err());
+        assert!(parse_queue_option("jobs:poll_until_message=maybe").is_err());
+        assert!(parse_queue_option("jobs:stream=true").is_ok());
+        assert!(parse_queue_option("jobs:batch_size=10").is_ok());
+        assert!(parse_queue_option("jobs:batch_size=0").is_err());
+        assert!(parse_queue_option("jobs:batch_size=11").is_err());
+        assert!(parse_queue_option("jobs:delimiter=json-lines").is_ok());
+        assert!(parse_queue_option("jobs:delimiter=tab").is_err());
+        assert!(parse_queue_option("jobs:write_mode=lines").is_ok());
+        assert!(parse_queue_option("jobs:binary=true").is_ok());
+        assert!(parse_queue_option("jobs.fifo:message_group_id=group").is_ok());
+        assert!(parse_queue_option("jobs:delay_seconds=900").is_ok());
+        assert!(parse_queue_option("jobs:delay_seconds=901").is_err());
+        assert!(parse_queue_option("jobs:unknown=1").is_err());
+        assert!(parse_queue_option("wait_time_secs=20").is_err());
+    }
+}
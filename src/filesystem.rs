@@ -1,214 +1,2786 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use aws_sdk_sqs::primitives::Blob;
+use aws_sdk_sqs::types::{Message, MessageAttributeValue, MessageSystemAttributeName, QueueAttributeName};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
 use fuser::{FileAttr, FileType};
+use fuser::consts::{FOPEN_DIRECT_IO, FOPEN_NONSEEKABLE};
 use libc::{getgid, getuid};
-use log::debug;
+use log::{debug, info, warn};
+use procfs::process::Process;
+use serde_json::json;
+use sha2::{Digest, Sha256};
 
-use crate::cli::CliArgs;
+use crate::backend::{MAX_BATCH_SIZE, MAX_DELAY_SECONDS, MAX_MESSAGE_ATTRIBUTES, MAX_MESSAGE_SIZE, OutgoingMessage, QueueBackend, is_valid_body};
+use crate::cli::{CliArgs, Delimiter, Layout, QueueSettings, ReadMode, WriteMode};
 use crate::sqs;
-use crate::sqs::SQSClient;
+use crate::spool::{ScheduledMessage, Spool};
+use crate::sqs::SQSError;
+
+/// Message attribute telling how the body of a message written to a binary queue was encoded
+const ENCODING_ATTRIBUTE: &str = "sqsfs-encoding";
+const BASE64_ENCODING: &str = "base64";
+
+/// Extended attributes setting the IDs of messages written to FIFO queues
+const GROUP_ID_XATTR: &str = "user.sqs.group_id";
+const DEDUPLICATION_ID_XATTR: &str = "user.sqs.deduplication_id";
+const DELAY_SECONDS_XATTR: &str = "user.sqs.delay_seconds";
+
+/// Message settings can also be given in the path a queue is opened with, e.g. `jobs@delay=300`.
+/// Each of them stands for an xattr
+const PATH_OPTIONS_SEPARATOR: char = '@';
+const PATH_OPTIONS: [(&str, &str); 2] = [("delay", DELAY_SECONDS_XATTR), ("group", GROUP_ID_XATTR)];
+
+/// Inode of the top level directory
+const ROOT_INODE: u64 = 1;
+
+/// Entries the superblock doesn't know about, e.g. queues opened with options in their path, get
+/// inodes on demand, starting at this one
+const DYNAMIC_INODE_START: u64 = 1 << 32;
+
+/// Directory messages are scheduled in, when there's a spool to keep them
+const SCHEDULED_DIR: &str = "scheduled";
+
+/// Prefix of the extended attributes turned into message attributes
+const ATTRIBUTE_XATTR_PREFIX: &str = "user.sqs.attr.";
+const ATTRIBUTE_DATA_TYPES: [&str; 3] = ["String", "Number", "Binary"];
+
+/// Extended attributes describing the last message read
+const MESSAGE_ID_XATTR: &str = "user.sqs.message_id";
+const RECEIVE_COUNT_XATTR: &str = "user.sqs.receive_count";
+const SENT_TIMESTAMP_XATTR: &str = "user.sqs.sent_timestamp";
+
+/// How long to wait before sending messages of a batch that SQS failed to send again, grows with
+/// each attempt
+const BATCH_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// How long the scheduler waits before trying to send a scheduled message that couldn't be sent
+/// again, and at most between two looks at the spool
+const SCHEDULER_RETRY_DELAY: Duration = Duration::from_secs(30);
+const SCHEDULER_MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Files of each queue in the directories layout
+const QUEUE_FILES: [(&str, Node); 7] = [
+    ("messages", Node::Messages),
+    ("peek", Node::Peek),
+    ("send", Node::Send),
+    ("stats", Node::Stats),
+    ("attributes", Node::Attributes),
+    (INFLIGHT_DIR, Node::InflightDir),
+    ("receive", Node::Receive),
+];
+
+/// Directory of each queue in the directories layout holding the messages received from it
+const INFLIGHT_DIR: &str = "inflight";
+
+/// Link to the dead-letter queue of a queue in the directories layout, if it has one
+const DLQ_LINK: &str = "dlq";
+
+/// What an inode stands for
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// Top level directory, listing the queues
+    Root,
+    /// Queue, read and written as a file (flat layout)
+    Queue,
+    /// Directory of a queue (directories layout)
+    QueueDir,
+    /// Messages of a queue, deleted according to the read mode
+    Messages,
+    /// Messages of a queue, left in it as they're read
+    Peek,
+    /// Messages written to a queue
+    Send,
+    /// Number of messages in a queue
+    Stats,
+    /// Directory listing the attributes of a queue
+    Attributes,
+    /// Attribute of a queue, e.g. VisibilityTimeout
+    Attribute(String),
+    /// Link to the dead-letter queue of a queue, by name
+    DeadLetterQueue(String),
+    /// Directory listing the messages received from a queue and not deleted yet
+    InflightDir,
+    /// Messages received into the inflight directory of a queue when read, listed by ID
+    Receive,
+    /// Message received from a queue, named after its ID
+    InflightMessage(String),
+    /// Directory listing the queues messages can be scheduled for
+    Scheduled,
+    /// Directory listing the messages scheduled for a queue
+    ScheduledQueue,
+    /// Message scheduled for a queue, named after the time it's due at
+    ScheduledMessage(String),
+}
+
+impl Node {
+    /// Whether reading and writing the file receives and sends messages
+    pub fn is_queue(&self) -> bool {
+        matches!(self, Node::Queue | Node::Messages | Node::Peek | Node::Send)
+    }
+
+    /// Whether the file can be opened with the given access mode
+    pub fn allows(&self, access_mask: u16) -> bool {
+        let allowed = match self {
+            Node::Queue | Node::Messages | Node::ScheduledMessage(_) => (libc::R_OK | libc::W_OK) as u16,
+            Node::Peek | Node::Stats | Node::Attribute(_) | Node::Receive | Node::InflightMessage(_) => libc::R_OK as u16,
+            Node::Send => libc::W_OK as u16,
+            _ => 0,
+        };
+        access_mask & !allowed == 0
+    }
+
+    fn file_attr(&self, inode: u64) -> FileAttr {
+        let kind = match self {
+            Node::Root | Node::QueueDir | Node::Attributes | Node::InflightDir | Node::Scheduled | Node::ScheduledQueue => FileType::Directory,
+            Node::DeadLetterQueue(_) => FileType::Symlink,
+            _ => FileType::RegularFile,
+        };
+
+        let mut file_attr = build_fileattr(inode, kind);
+        match self {
+            Node::DeadLetterQueue(name) => {
+                file_attr.perm = 0o777;
+                file_attr.size = dlq_link_target(name).len() as u64;
+            }
+            _ if kind != FileType::RegularFile => {}
+            _ if !self.allows(libc::W_OK as u16) => file_attr.perm = 0o444,
+            _ if !self.allows(libc::R_OK as u16) => file_attr.perm = 0o200,
+            _ => {}
+        }
+        file_attr
+    }
+}
 
 #[derive(Clone)]
 pub struct Metadata {
     pub queue_name: String,
     pub queue_url: String,
     pub file_attr: FileAttr,
+    pub node: Node,
+    /// Path from the top level directory, e.g. `jobs/send`
+    path: String,
+    /// Message settings given in the path the queue was looked up with
+    path_options: MessageOptions,
 }
 
-struct FileHandleState {
+/// Message handed to the reader but not deleted yet
+struct Inflight {
+    receipt_handle: String,
+    /// Offset right past the message's data
+    end: u64,
+}
+
+/// Message received into an `inflight` directory
+struct ReceivedMessage {
+    message: Message,
+    /// When the visibility timeout of the message lapses, after which it can't be deleted
+    /// anymore and shows up in the queue again
+    visible_at: Instant,
+}
+
+/// Settings of the messages written through a handle
+#[derive(Clone, Default)]
+struct MessageOptions {
+    group_id: Option<String>,
+    deduplication_id: Option<String>,
+    attributes: HashMap<String, MessageAttributeValue>,
+    delay_seconds: Option<i32>,
+}
+
+/// Open file. What doesn't change while the file is open is kept out of the lock, so that it can
+/// be looked at while the handle is busy, e.g. waiting on a long poll
+struct FileHandle {
     mode: u16,
+    ino: u64,
+    /// What was opened
+    node: Node,
+    /// Process that opened the file
+    pid: u32,
+    state: Mutex<FileHandleState>,
 }
 
+struct FileHandleState {
+    queue_name: String,
+    queue_url: String,
+    /// When messages read through this handle get deleted
+    read_mode: ReadMode,
+    /// Messages received through this handle, oldest first (at-least-once and peek modes)
+    inflight: Vec<Inflight>,
+    /// Received data not consumed yet, starting at offset `buffer_start` of the file
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    /// Messages making up the buffer, along with the offset right past their data
+    buffered_messages: Vec<(u64, Message)>,
+    /// Message the reader got to last, exposed through xattrs
+    last_message: Option<Message>,
+    /// Whether messages were received through this handle already
+    received: bool,
+    /// Whether the reader asked for data past the end of the buffer (at-least-once mode)
+    fully_read: bool,
+    /// Data written through this handle and not sent yet, starting at offset `write_start` of
+    /// the file
+    write_buffer: Vec<u8>,
+    write_start: u64,
+    options: MessageOptions,
+    /// Number of messages sent through this handle so far
+    messages_sent: u64,
+    /// Whether the FIFO queue deduplicates messages based on their body, looked up when needed
+    content_based_deduplication: Option<bool>,
+}
+
+/// Inodes of the entries the superblock doesn't know about, handed out on demand and never
+/// reused
+struct DynamicInodes {
+    inodes: HashMap<String, u64>,
+    paths: HashMap<u64, String>,
+    next_inode: u64,
+}
+
+impl DynamicInodes {
+    /// Inode of the entry at `path`, given one if it doesn't have one yet
+    fn inode(&mut self, path: &str) -> u64 {
+        if let Some(inode) = self.inodes.get(path) {
+            return *inode;
+        }
+
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(path.to_string(), inode);
+        self.paths.insert(inode, path.to_string());
+        inode
+    }
+
+    /// Forget about an entry that's gone
+    fn remove(&mut self, path: &str) {
+        if let Some(inode) = self.inodes.remove(path) {
+            self.paths.remove(&inode);
+        }
+    }
+}
+
+/// Inode of each entry, by parent inode and name
+type AuxMap = BTreeMap<(u64, String), u64>;
+
+/// Queue created or deleted through the mount point, which listing queues may not show yet
+enum QueueChange {
+    /// Created, with its name
+    Created(String),
+    Deleted,
+}
+
+/// Cached view of the queues, rebuilt every `cache_ttl_in_secs`
+struct Superblock {
+    inodes: BTreeMap<u64, Metadata>,
+    aux_map: AuxMap,
+    last_refresh: SystemTime,
+}
+
+impl Superblock {
+    /// Add an entry to the directory with inode `parent`, returning the inode of the entry
+    fn add(&mut self, parent: u64, name: &str, queue_name: &str, queue_url: &str, node: Node) -> u64 {
+        let ino = self.inodes.last_key_value().map_or(ROOT_INODE, |(ino, _)| *ino) + 1;
+
+        let path = child_path(&self.inodes[&parent], name);
+        self.inodes.insert(ino, Metadata {
+            queue_name: queue_name.to_string(),
+            queue_url: queue_url.to_string(),
+            file_attr: node.file_attr(ino),
+            node,
+            path,
+            path_options: MessageOptions::default(),
+        });
+        self.aux_map.insert((parent, name.to_string()), ino);
+        ino
+    }
+
+    /// Remove all the entries of a queue
+    fn remove_queue(&mut self, queue_url: &str) {
+        self.inodes.retain(|_, metadata| metadata.queue_url != queue_url);
+
+        let inodes = &self.inodes;
+        self.aux_map.retain(|_, ino| inodes.contains_key(ino));
+    }
+}
+
+/// FUSE requests are served concurrently, hence all state is behind locks. File handles are
+/// locked individually so a slow operation on a handle doesn't hold up the other ones.
 pub struct SQSFileSystem {
-    superblock: BTreeMap<u64, Metadata>,
-    aux_map: BTreeMap<String, u64>,
-    fh_map: HashMap<u64, FileHandleState>, //TODO add Lock
+    superblock: RwLock<Superblock>,
+    fh_map: Mutex<HashMap<u64, Arc<FileHandle>>>,
     next_file_handle: AtomicU64,
-    sqsclient: SQSClient,
-    last_refresh: SystemTime,
+    /// Message settings of each queue, by URL, set through xattrs while the queue wasn't open
+    message_options: Mutex<HashMap<String, MessageOptions>>,
+    /// Message read last from each queue, by URL, whatever the handle
+    last_messages: Mutex<HashMap<String, Message>>,
+    /// Messages received into the `inflight` directory of each queue, by URL and message ID
+    received_messages: Mutex<HashMap<String, BTreeMap<String, ReceivedMessage>>>,
+    /// Entries given an inode on demand, by path and by inode
+    dynamic_inodes: Mutex<DynamicInodes>,
+    /// Queues created or deleted through the mount point, by URL, until listing queues agrees
+    queue_changes: Mutex<HashMap<String, QueueChange>>,
+    /// Messages scheduled for later, if enabled
+    spool: Option<Spool>,
+    backend: Box<dyn QueueBackend>,
     cli_args: CliArgs,
 }
 
 impl SQSFileSystem {
-    pub fn new(cli_args: CliArgs) -> Self {
-        SQSFileSystem {
-            superblock: BTreeMap::new(),
-            aux_map: BTreeMap::new(),
-            fh_map: HashMap::new(),
+    pub fn new(cli_args: CliArgs, backend: Box<dyn QueueBackend>) -> anyhow::Result<Self> {
+        let spool = cli_args.spool_dir.clone()
+            .map(Spool::new)
+            .transpose()?;
+
+        Ok(SQSFileSystem {
+            superblock: RwLock::new(Superblock {
+                inodes: BTreeMap::new(),
+                aux_map: BTreeMap::new(),
+                last_refresh: UNIX_EPOCH,
+            }),
+            fh_map: Mutex::new(HashMap::new()),
             next_file_handle: AtomicU64::default(),
-            sqsclient: SQSClient::new(),
-            last_refresh: UNIX_EPOCH,
+            message_options: Mutex::new(HashMap::new()),
+            last_messages: Mutex::new(HashMap::new()),
+            received_messages: Mutex::new(HashMap::new()),
+            dynamic_inodes: Mutex::new(DynamicInodes {
+                inodes: HashMap::new(),
+                paths: HashMap::new(),
+                next_inode: DYNAMIC_INODE_START,
+            }),
+            queue_changes: Mutex::new(HashMap::new()),
+            spool,
+            backend,
             cli_args,
-        }
+        })
     }
 
-    fn refresh(&mut self) {
+    fn refresh(&self) -> anyhow::Result<()> {
         // check if we need to refresh the cache or if we can use what we have
-        if self.last_refresh.elapsed().unwrap() < Duration::from_secs(self.cli_args.cache_ttl_in_secs) {
+        let last_refresh = self.superblock.read().unwrap().last_refresh;
+        if last_refresh.elapsed().unwrap_or_default() < Duration::from_secs(self.cli_args.cache_ttl_in_secs) {
             debug!("no need to refresh the cache");
-            return;
+            return Ok(());
         } else {
             debug!("refreshing the cache");
         }
 
-        // purge local cache
-        self.aux_map.clear();
-        self.superblock.clear();
+        // populate a new cache without holding the lock, so other requests can still use the
+        // current one while we wait on SQS
+        let mut superblock = self.do_refresh()?;
 
-        // populate cache
-        self.do_refresh();
+        // swap caches, along with the queues created or deleted meanwhile: they're recorded
+        // while holding the lock, so none of them can be missed
+        let mut current = self.superblock.write().unwrap();
+        self.apply_queue_changes(&mut superblock);
+        *current = superblock;
+        Ok(())
+    }
+
+    /// Apply the queues created or deleted through the mount point over a fresh listing, which may
+    /// lag behind for a while, forgetting about the changes the listing agrees with
+    fn apply_queue_changes(&self, superblock: &mut Superblock) {
+        let listed: HashSet<String> = superblock.inodes.values()
+            .map(|metadata| metadata.queue_url.clone())
+            .collect();
 
-        // update cache control
-        self.last_refresh = SystemTime::now();
+        self.queue_changes.lock().unwrap().retain(|queue_url, change| match change {
+            QueueChange::Created(queue_name) if !listed.contains(queue_url) => {
+                self.add_queue(superblock, queue_name, queue_url);
+                true
+            }
+            QueueChange::Deleted if listed.contains(queue_url) => {
+                superblock.remove_queue(queue_url);
+                true
+            }
+            _ => false,
+        });
     }
 
-    fn do_refresh(&mut self) {
+    fn do_refresh(&self) -> anyhow::Result<Superblock> {
+        let mut superblock = Superblock {
+            inodes: BTreeMap::new(),
+            aux_map: BTreeMap::new(),
+            last_refresh: SystemTime::now(),
+        };
+
         // add top level directory
-        self.superblock.insert(1, Metadata {
+        superblock.inodes.insert(ROOT_INODE, Metadata {
             queue_name: ".".to_string(),
             queue_url: "".to_string(),
-            file_attr: build_fileattr(1, FileType::Directory),
+            file_attr: Node::Root.file_attr(ROOT_INODE),
+            node: Node::Root,
+            path: "".to_string(),
+            path_options: MessageOptions::default(),
         });
 
         // fetch queues
-        if let Ok(queues) = self.sqsclient.list_queues() {
-            // add queues
-            let mut fake_ino = 2u64;
-            for queue in queues {
-                let queue_name = sqs::get_queue_name(queue.as_str()).unwrap();
-
-                self.superblock.insert(fake_ino, Metadata {
-                    queue_name: queue_name.clone(),
-                    queue_url: queue,
-                    file_attr: build_fileattr(fake_ino, FileType::RegularFile),
-                });
+        let queues = self.backend.list_queues()
+            .context("Error while listing queues")?;
+
+        let flat = self.cli_args.layout == Layout::Flat;
+        let queues: Vec<(String, String)> = queues.into_iter().filter_map(|queue| {
+            match sqs::get_queue_name(queue.as_str()) {
+                Ok(queue_name) if flat && self.spool.is_some() && queue_name == SCHEDULED_DIR => {
+                    warn!("skipping queue: {} is where messages are scheduled", queue_name);
+                    None
+                }
+                Ok(queue_name) => Some((queue_name, queue)),
+                Err(error) => {
+                    warn!("skipping queue: {}", error);
+                    None
+                }
+            }
+        }).collect();
+
+        // add the directory listing the queues messages can be scheduled for
+        if flat && self.spool.is_some() {
+            superblock.add(ROOT_INODE, SCHEDULED_DIR, SCHEDULED_DIR, "", Node::Scheduled);
+        }
+
+        for (queue_name, queue) in &queues {
+            self.add_queue(&mut superblock, queue_name, queue);
+        }
 
-                self.aux_map.insert(queue_name, fake_ino);
+        Ok(superblock)
+    }
+
+    /// Add the entries of a queue, returning the inode of the queue itself
+    fn add_queue(&self, superblock: &mut Superblock, queue_name: &str, queue_url: &str) -> u64 {
+        match self.cli_args.layout {
+            // add the queue, along with a directory to schedule messages in
+            Layout::Flat => {
+                let scheduled_ino = superblock.aux_map.get(&(ROOT_INODE, SCHEDULED_DIR.to_string())).copied();
+                if let Some(scheduled_ino) = scheduled_ino {
+                    superblock.add(scheduled_ino, queue_name, queue_name, queue_url, Node::ScheduledQueue);
+                }
+                superblock.add(ROOT_INODE, queue_name, queue_name, queue_url, Node::Queue)
+            }
+            // add a directory for the queue, holding its files and the messages scheduled for it
+            Layout::Directories => {
+                let queue_ino = superblock.add(ROOT_INODE, queue_name, queue_name, queue_url, Node::QueueDir);
+                for (name, node) in QUEUE_FILES {
+                    superblock.add(queue_ino, name, queue_name, queue_url, node);
+                }
 
-                fake_ino += 1;
+                if self.spool.is_some() {
+                    superblock.add(queue_ino, SCHEDULED_DIR, queue_name, queue_url, Node::ScheduledQueue);
+                }
+                queue_ino
             }
         }
     }
-    pub fn list_files(&mut self) -> Vec<&Metadata> {
-        let mut files = vec![];
 
-        // refresh cache if needed
-        self.refresh();
+    /// Entries of a directory along with their names, or None if the inode isn't a directory
+    pub fn list_files(&self, inode: u64) -> anyhow::Result<Option<Vec<(String, Metadata)>>> {
+        let Some(parent) = self.find_by_inode(inode)? else {
+            return Ok(None);
+        };
+        if parent.file_attr.kind != FileType::Directory {
+            return Ok(None);
+        }
 
-        for item in self.superblock.values() {
-            if item.file_attr.ino != 1 {
-                files.push(item)
+        let mut files: Vec<(String, Metadata)> = {
+            let superblock = self.superblock.read().unwrap();
+            superblock.aux_map.range((inode, String::new())..)
+                .take_while(|((parent, _), _)| *parent == inode)
+                .filter_map(|((_, name), child)| Some((name.clone(), superblock.inodes.get(child)?.clone())))
+                .collect()
+        };
+
+        // entries that depend on the state of the queue are looked up every time
+        match (&parent.node, &self.spool) {
+            (Node::QueueDir, _) => {
+                if let Some(metadata) = self.find_child(&parent, DLQ_LINK)? {
+                    files.push((DLQ_LINK.to_string(), metadata));
+                }
+            }
+            (Node::Attributes, _) => {
+                for name in self.queue_attributes(&parent)?.into_keys() {
+                    let node = Node::Attribute(name.clone());
+                    files.push((name.clone(), self.dynamic_metadata(&parent, &name, node)));
+                }
+            }
+            (Node::InflightDir, _) => {
+                for msg in self.received_messages(&parent) {
+                    let metadata = self.inflight_metadata(&parent, &msg);
+                    files.push((msg.message_id().unwrap_or_default().to_string(), metadata));
+                }
+            }
+            (Node::ScheduledQueue, Some(spool)) => {
+                for message in spool.list(&parent.queue_name)? {
+                    files.push((message.name.clone(), self.scheduled_metadata(&parent, &message)));
+                }
             }
+            _ => {}
         }
 
-        files
+        Ok(Some(files))
     }
 
-    pub fn has_file(&self, file_name: &String) -> bool {
-        self.aux_map.contains_key(file_name)
+    pub fn find_by_name(&self, parent: u64, file_name: &str) -> anyhow::Result<Option<Metadata>> {
+        match self.find_by_inode(parent)? {
+            Some(parent) => self.find_child(&parent, file_name),
+            None => Ok(None),
+        }
     }
-    pub fn find_by_name(&mut self, file_name: &String) -> Option<&Metadata> {
+
+    pub fn find_by_inode(&self, inode: u64) -> anyhow::Result<Option<Metadata>> {
         // refresh cache if needed
-        self.refresh();
+        self.refresh()?;
 
-        if !self.has_file(file_name) {
-            return None;
+        if inode >= DYNAMIC_INODE_START {
+            let path = self.dynamic_inodes.lock().unwrap().paths.get(&inode).cloned();
+            return match path {
+                Some(path) => self.find_by_path(&path),
+                None => Ok(None),
+            };
         }
 
-        self.superblock.get(self.aux_map.get(file_name).unwrap())
+        Ok(self.superblock.read().unwrap().inodes.get(&inode).cloned())
     }
 
-    pub fn find_by_inode(&mut self, inode: u64) -> Option<&Metadata> {
-        // refresh cache if needed
-        self.refresh();
+    /// Look up an entry by its path from the top level directory, e.g. `scheduled/jobs/<time>`
+    fn find_by_path(&self, path: &str) -> anyhow::Result<Option<Metadata>> {
+        let mut metadata = self.find_by_inode(ROOT_INODE)?;
+        for name in path.split('/') {
+            metadata = match metadata {
+                Some(parent) => self.find_child(&parent, name)?,
+                None => return Ok(None),
+            };
+        }
+        Ok(metadata)
+    }
+
+    fn find_child(&self, parent: &Metadata, name: &str) -> anyhow::Result<Option<Metadata>> {
+        {
+            let superblock = self.superblock.read().unwrap();
+            if let Some(inode) = superblock.aux_map.get(&(parent.file_attr.ino, name.to_string())) {
+                return Ok(superblock.inodes.get(inode).cloned());
+            }
+        }
+
+        match (&parent.node, &self.spool) {
+            (Node::Root | Node::QueueDir, _) if name.contains(PATH_OPTIONS_SEPARATOR) => self.find_alias(parent, name),
+            (Node::QueueDir, _) if name == DLQ_LINK => Ok(self.queue_attributes(parent)?
+                .get(QueueAttributeName::RedrivePolicy.as_str())
+                .and_then(|redrive_policy| dead_letter_queue_name(redrive_policy))
+                .map(|dlq_name| self.dynamic_metadata(parent, name, Node::DeadLetterQueue(dlq_name)))),
+            (Node::Attributes, _) => Ok(self.queue_attributes(parent)?
+                .contains_key(name)
+                .then(|| self.dynamic_metadata(parent, name, Node::Attribute(name.to_string())))),
+            (Node::InflightDir, _) => Ok(self.received_message(&parent.queue_url, name)
+                .map(|msg| self.inflight_metadata(parent, &msg))),
+            (Node::ScheduledQueue, Some(spool)) => Ok(spool.find(&parent.queue_name, name)?
+                .map(|message| self.scheduled_metadata(parent, &message))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Look up a queue along with the options given in its path, e.g. `jobs@delay=300`
+    fn find_alias(&self, parent: &Metadata, alias: &str) -> anyhow::Result<Option<Metadata>> {
+        let Some((queue_name, options)) = alias.split_once(PATH_OPTIONS_SEPARATOR) else {
+            return Ok(None);
+        };
+
+        let mut path_options = MessageOptions::default();
+        for option in options.split(',') {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            let name = PATH_OPTIONS.iter()
+                .find(|(path_option, _)| *path_option == key)
+                .map(|(_, name)| *name)
+                .ok_or_else(|| anyhow!("unknown option in path: {}", alias).context(SQSError::InvalidInput))?;
+            set_message_option(&mut path_options, name, Some(value.to_string()))?;
+        }
+        check_own_delay(queue_name, path_options.delay_seconds.is_some())?;
+
+        let metadata = {
+            let superblock = self.superblock.read().unwrap();
+            superblock.aux_map.get(&(parent.file_attr.ino, queue_name.to_string()))
+                .and_then(|inode| superblock.inodes.get(inode))
+                .filter(|metadata| metadata.node.is_queue())
+                .cloned()
+        };
+
+        Ok(metadata.map(|mut metadata| {
+            metadata.path = child_path(parent, alias);
+            metadata.file_attr.ino = self.dynamic_inode(&metadata.path);
+            metadata.path_options = path_options;
+            metadata
+        }))
+    }
+
+    fn scheduled_metadata(&self, parent: &Metadata, message: &ScheduledMessage) -> Metadata {
+        let mut metadata = self.dynamic_metadata(parent, &message.name, Node::ScheduledMessage(message.name.clone()));
+        metadata.file_attr.size = message.size;
+        metadata.file_attr.blocks = message.size.div_ceil(metadata.file_attr.blksize as u64);
+        metadata
+    }
+
+    fn inflight_metadata(&self, parent: &Metadata, msg: &Message) -> Metadata {
+        let message_id = msg.message_id().unwrap_or_default();
+        let mut metadata = self.dynamic_metadata(parent, message_id, Node::InflightMessage(message_id.to_string()));
+        metadata.file_attr.size = self.inflight_body(&metadata.queue_name, msg).len() as u64;
+        metadata.file_attr.blocks = metadata.file_attr.size.div_ceil(metadata.file_attr.blksize as u64);
+        metadata
+    }
+
+    /// Metadata of an entry the superblock doesn't know about
+    fn dynamic_metadata(&self, parent: &Metadata, name: &str, node: Node) -> Metadata {
+        let path = child_path(parent, name);
+        Metadata {
+            queue_name: parent.queue_name.clone(),
+            queue_url: parent.queue_url.clone(),
+            file_attr: node.file_attr(self.dynamic_inode(&path)),
+            node,
+            path,
+            path_options: MessageOptions::default(),
+        }
+    }
+
+    /// Inode of an entry the superblock doesn't know about, allocated the first time it's needed
+    fn dynamic_inode(&self, path: &str) -> u64 {
+        self.dynamic_inodes.lock().unwrap().inode(path)
+    }
+
+    /// Attributes of the queue, by name
+    fn queue_attributes(&self, metadata: &Metadata) -> anyhow::Result<BTreeMap<String, String>> {
+        let attributes = self.backend.get_queue_attributes(&metadata.queue_url)
+            .context(format!("Error while getting attributes of: {}", metadata.queue_url))?;
+
+        Ok(attributes.into_iter()
+            .map(|(name, value)| (name.as_str().to_string(), value))
+            .collect())
+    }
+
+    /// Target of a link to a dead-letter queue
+    pub fn read_link(&self, metadata: &Metadata) -> Option<String> {
+        match &metadata.node {
+            Node::DeadLetterQueue(name) => Some(dlq_link_target(name)),
+            _ => None,
+        }
+    }
 
-        self.superblock.get(&inode)
+    /// Flags to hand over to the kernel when a queue is opened
+    pub fn open_flags(&self, metadata: &Metadata) -> u32 {
+        // the content of a queue depends on the handle it's read through, so bypass the page
+        // cache: every read reaches us with its real offset and size and is served from the
+        // handle's own buffer
+        match metadata.node.is_queue() && self.cli_args.queue_settings(&metadata.queue_name).stream {
+            // streams have no end and can't be rewound
+            true => FOPEN_DIRECT_IO | FOPEN_NONSEEKABLE,
+            false => FOPEN_DIRECT_IO,
+        }
     }
 
-    pub fn read(&self, metadata: &Metadata) -> anyhow::Result<String> {
-        let result = self.sqsclient.receive_message(metadata.queue_url.as_str());
+    /// Read `size` bytes at `offset` from the queue on behalf of process `pid`. Messages are
+    /// received into the handle's buffer and served from there, so a message bigger than a single
+    /// read is delivered across several of them. Once the buffer is consumed, a stream receives
+    /// more messages while a regular handle returns EOF.
+    pub fn read(&self, fh: u64, metadata: &Metadata, offset: i64, size: u32, pid: u32) -> anyhow::Result<Vec<u8>> {
+        match (&metadata.node, &self.spool) {
+            // scheduled messages are plain files until they're due
+            (Node::ScheduledMessage(name), Some(spool)) => return spool.read(&metadata.queue_name, name, offset as u64, size),
+            (Node::Stats | Node::Attribute(_), _) => return self.read_generated(fh, offset as u64, size, || self.queue_info(metadata)),
+            // one message ID per line, e.g. for a shell loop over the files of the inflight directory
+            (Node::Receive, _) => return self.read_generated(fh, offset as u64, size, || Ok(self.receive_inflight(metadata, pid)?
+                .iter()
+                .map(|message_id| format!("{}\n", message_id))
+                .collect::<String>()
+                .into_bytes())),
+            (Node::InflightMessage(message_id), _) => {
+                let msg = self.received_message(&metadata.queue_url, message_id)
+                    .ok_or_else(|| anyhow!("message {} is visible again, it can't be read anymore", message_id)
+                        .context(SQSError::InvalidInput))?;
+                let body = self.inflight_body(&metadata.queue_name, &msg);
+
+                let start = (offset as usize).min(body.len());
+                let end = (start + size as usize).min(body.len());
+                return Ok(body[start..end].to_vec());
+            }
+            _ => {}
+        }
+
+        let settings = self.cli_args.queue_settings(&metadata.queue_name);
+        let stream = settings.stream;
+        let handle = self.get_file_handler(fh)
+            .ok_or_else(|| anyhow!("Unknown file handle: {}", fh))?;
+        let mut handle = handle.state.lock().unwrap();
+        let offset = offset as u64;
+
+        // the reader asking for data at `offset` means it's done with everything before it
+        if stream && handle.read_mode == ReadMode::AtLeastOnce {
+            self.delete_inflight_until(&mut handle, offset)?;
+        }
+
+        let buffer_end = handle.buffer_start + handle.buffer.len() as u64;
+        if offset >= buffer_end && (stream || !handle.received) {
+            handle.buffer.clear();
+            handle.buffer_start = buffer_end;
+            handle.buffered_messages.clear();
+            handle.received = true;
+
+            let mut received = vec![];
+            for msg in self.receive(metadata, pid)? {
+                append_message(&mut handle.buffer, &msg, &settings);
+                let end = handle.buffer_start + handle.buffer.len() as u64;
 
-        if let Ok(receive_message_output) = result {
-            if let Some(msg) = receive_message_output.messages().first() {
                 if let Some(receipt_handle) = msg.receipt_handle() {
-                    // delete message from SQS before responding to user
-                    self.sqsclient.delete_message(
-                        metadata.queue_url.as_str(),
-                        receipt_handle,
-                    ).context(format!("Error while deleting message with receipt handle: {}", receipt_handle))?;
+                    received.push(Inflight {
+                        receipt_handle: receipt_handle.to_string(),
+                        end,
+                    });
                 }
+                handle.buffered_messages.push((end, msg));
+            }
+            self.track_received(&mut handle, metadata, received, stream)?;
+        }
 
-                if let Some(body) = msg.body() {
-                    return Ok(body.to_string());
-                }
+        let start = (offset.saturating_sub(handle.buffer_start) as usize).min(handle.buffer.len());
+        let end = (start + size as usize).min(handle.buffer.len());
+        let data = handle.buffer[start..end].to_vec();
+
+        // anything past the end of the buffer is EOF
+        if data.is_empty() {
+            handle.fully_read = true;
+        } else {
+            // the message holding the last byte handed over
+            let read_end = handle.buffer_start + end as u64;
+            let last_message = handle.buffered_messages.iter()
+                .find(|(message_end, _)| *message_end >= read_end)
+                .map(|(_, msg)| msg.clone());
+
+            if let Some(msg) = last_message {
+                self.last_messages.lock().unwrap().insert(metadata.queue_url.clone(), msg.clone());
+                handle.last_message = Some(msg);
             }
         }
+        Ok(data)
+    }
+
+    /// Read a file whose content is generated on the first read, so that it doesn't change while
+    /// being read, e.g. a file describing the queue
+    fn read_generated<F>(&self, fh: u64, offset: u64, size: u32, generate: F) -> anyhow::Result<Vec<u8>>
+    where
+        F: FnOnce() -> anyhow::Result<Vec<u8>>,
+    {
+        let handle = self.get_file_handler(fh)
+            .ok_or_else(|| anyhow!("Unknown file handle: {}", fh))?;
+        let mut handle = handle.state.lock().unwrap();
 
+        if !handle.received {
+            handle.buffer = generate()?;
+            handle.received = true;
+        }
 
-        Ok("".to_string())
+        let start = (offset as usize).min(handle.buffer.len());
+        let end = (start + size as usize).min(handle.buffer.len());
+        Ok(handle.buffer[start..end].to_vec())
     }
 
-    pub fn write(&self, metadata: &Metadata, data: &str) -> anyhow::Result<u32> {
-        self.sqsclient.send_message(metadata.queue_url.as_str(), data)
+    fn queue_info(&self, metadata: &Metadata) -> anyhow::Result<Vec<u8>> {
+        let attributes = self.queue_attributes(metadata)?;
+        let count = |name: QueueAttributeName| attributes.get(name.as_str())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or_default();
+
+        let info = match &metadata.node {
+            Node::Attribute(name) => attributes.get(name).cloned().unwrap_or_default(),
+            _ => json!({
+                "visible": count(QueueAttributeName::ApproximateNumberOfMessages),
+                "in_flight": count(QueueAttributeName::ApproximateNumberOfMessagesNotVisible),
+                "delayed": count(QueueAttributeName::ApproximateNumberOfMessagesDelayed),
+            }).to_string(),
+        };
+        Ok(format!("{}\n", info).into_bytes())
     }
 
-    pub fn create_file_handler(&mut self, mode: u16) -> u64 {
-        let fh = self.next_file_handle.fetch_add(1, Ordering::SeqCst);
-        self.fh_map.insert(fh, FileHandleState {
-            mode,
+    /// Receive messages into the `inflight` directory of a queue, returning their IDs. They're
+    /// listed there until they're deleted by removing them, or until their visibility timeout
+    /// lapses and they go back to the queue
+    fn receive_inflight(&self, metadata: &Metadata, pid: u32) -> anyhow::Result<Vec<String>> {
+        // the visibility timeout starts when SQS hands the messages over, so take it from there
+        let visibility_timeout = self.queue_attributes(metadata)?
+            .get(QueueAttributeName::VisibilityTimeout.as_str())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or_default();
+        let messages = self.receive(metadata, pid)?;
+        let visible_at = Instant::now() + Duration::from_secs(visibility_timeout);
+
+        let mut received_messages = self.received_messages.lock().unwrap();
+        let received_messages = received_messages.entry(metadata.queue_url.clone()).or_default();
+        self.forget_expired(&metadata.queue_name, received_messages);
+
+        let mut message_ids = vec![];
+        for msg in messages {
+            if let Some(message_id) = msg.message_id() {
+                message_ids.push(message_id.to_string());
+                received_messages.insert(message_id.to_string(), ReceivedMessage {
+                    message: msg.clone(),
+                    visible_at,
+                });
+            }
+        }
+        Ok(message_ids)
+    }
+
+    /// Messages received into the `inflight` directory of a queue whose visibility timeout
+    /// hasn't lapsed yet, forgetting about the other ones
+    fn received_messages(&self, metadata: &Metadata) -> Vec<Message> {
+        let mut received_messages = self.received_messages.lock().unwrap();
+        let Some(received_messages) = received_messages.get_mut(&metadata.queue_url) else {
+            return vec![];
+        };
+
+        self.forget_expired(&metadata.queue_name, received_messages);
+        received_messages.values()
+            .map(|received| received.message.clone())
+            .collect()
+    }
+
+    /// Forget about received messages whose visibility timeout lapsed, along with their files
+    fn forget_expired(&self, queue_name: &str, received_messages: &mut BTreeMap<String, ReceivedMessage>) {
+        let now = Instant::now();
+        let mut dynamic_inodes = self.dynamic_inodes.lock().unwrap();
+        received_messages.retain(|message_id, received| {
+            let expired = received.visible_at <= now;
+            if expired {
+                dynamic_inodes.remove(&format!("{}/{}/{}", queue_name, INFLIGHT_DIR, message_id));
+            }
+            !expired
         });
-        fh
     }
 
-    pub fn check_file_handler_mode(&self, fh: u64, mode: u16) -> bool {
-        match self.fh_map.get(&fh) {
-            Some(entry) => entry.mode & mode > 0,
-            None => false,
+    fn received_message(&self, queue_url: &str, message_id: &str) -> Option<Message> {
+        self.received_messages.lock().unwrap()
+            .get(queue_url)
+            .and_then(|received_messages| received_messages.get(message_id))
+            .filter(|received| received.visible_at > Instant::now())
+            .map(|received| received.message.clone())
+    }
+
+    /// Content of a file in an `inflight` directory, the body of the message alone
+    fn inflight_body(&self, queue_name: &str, msg: &Message) -> Vec<u8> {
+        message_body(msg, self.cli_args.queue_settings(queue_name).binary)
+    }
+
+    fn receive(&self, metadata: &Metadata, pid: u32) -> anyhow::Result<Vec<Message>> {
+        let settings = self.cli_args.queue_settings(&metadata.queue_name);
+        // there's no EOF in a stream, so wait for as long as it takes to get more data
+        let poll_until_message = settings.poll_until_message || settings.stream;
+
+        // polling until a message arrives with a wait time of zero would hammer SQS
+        let wait_time_secs = match poll_until_message {
+            true => settings.wait_time_secs.max(1),
+            false => settings.wait_time_secs,
+        };
+
+        loop {
+            let messages = self.backend.receive_message(metadata.queue_url.as_str(), settings.batch_size, wait_time_secs)
+                .context(format!("Error while receiving message from: {}", metadata.queue_url))?;
+
+            if !messages.is_empty() || !poll_until_message {
+                return Ok(messages);
+            }
+
+            if reader_interrupted(pid) {
+                return Err(anyhow!("Stopped polling {} on behalf of pid {}", metadata.queue_url, pid)
+                    .context(SQSError::Interrupted));
+            }
         }
     }
 
-    pub fn release_file_handler(&mut self, fh: u64) {
-        self.fh_map.remove(&fh);
+    /// Deal with the messages received through a handle according to the read mode
+    fn track_received(&self, handle: &mut FileHandleState, metadata: &Metadata, received: Vec<Inflight>, stream: bool) -> anyhow::Result<()> {
+        match handle.read_mode {
+            // delete messages from SQS before responding to user
+            ReadMode::Consume => {
+                let receipt_handles: Vec<String> = received.into_iter()
+                    .map(|inflight| inflight.receipt_handle)
+                    .collect();
+                self.delete_messages(metadata.queue_url.as_str(), &receipt_handles)
+            }
+            // put the messages back straight away so they stay in the queue. Streams keep them
+            // until the handle is released instead, so that they don't show them over and over
+            ReadMode::Peek if !stream => {
+                for inflight in received {
+                    self.backend.change_message_visibility(metadata.queue_url.as_str(), &inflight.receipt_handle, 0)
+                        .context(format!("Error while releasing message with receipt handle: {}", inflight.receipt_handle))?;
+                }
+                Ok(())
+            }
+            // messages are only deleted (or released, when peeking) once the reader is done
+            // with them, see release_file_handler
+            ReadMode::AtLeastOnce | ReadMode::Peek => {
+                handle.inflight.extend(received);
+                Ok(())
+            }
+        }
     }
-}
 
-fn build_fileattr(inode: u64, kind: FileType) -> FileAttr {
-    let size: u64;
-    let perm: u16;
-    let nlink: u32;
-    let blksize: u32 = 512;
+    /// Delete messages using as few requests as possible
+    fn delete_messages(&self, queue_url: &str, receipt_handles: &[String]) -> anyhow::Result<()> {
+        for chunk in receipt_handles.chunks(MAX_BATCH_SIZE) {
+            match chunk {
+                [receipt_handle] => self.backend.delete_message(queue_url, receipt_handle)
+                    .context(format!("Error while deleting message with receipt handle: {}", receipt_handle))?,
+                _ => self.backend.delete_message_batch(queue_url, chunk)
+                    .context(format!("Error while deleting {} messages from: {}", chunk.len(), queue_url))?,
+            }
+        }
+        Ok(())
+    }
 
-    match kind {
-        FileType::Directory => {
-            size = 0;
-            perm = 0o755;
-            nlink = 2;
+    /// Write `data` at `offset` of the message being put together through this handle. The
+    /// message is only sent once the handle is flushed, i.e. when the writer closes the file,
+    /// except in lines mode where complete lines go out as soon as they fill a batch. Writes in
+    /// append mode go at the end of the message whatever their offset.
+    pub fn write(&self, fh: u64, offset: i64, data: &[u8], append: bool) -> anyhow::Result<u32> {
+        let handle = self.get_file_handler(fh)
+            .ok_or_else(|| anyhow!("Unknown file handle: {}", fh))?;
+        if let (Node::ScheduledMessage(name), Some(spool)) = (&handle.node, &self.spool) {
+            let queue_name = handle.state.lock().unwrap().queue_name.clone();
+            return self.write_scheduled(spool, &queue_name, name, offset as u64, data, append);
         }
-        _ => {
-            size = 1 * 1024 * 1024;
-            perm = 0o644;
-            nlink = 1;
+        let mut handle = handle.state.lock().unwrap();
+        let settings = self.cli_args.queue_settings(&handle.queue_name);
+
+        let start = match append {
+            true => handle.write_buffer.len(),
+            false => (offset as u64).checked_sub(handle.write_start)
+                .ok_or_else(|| anyhow!("data written to {} at offset {} was sent already", handle.queue_url, offset)
+                    .context(SQSError::InvalidInput))? as usize,
+        };
+        let end = start + data.len();
+
+        // base64 takes 4 bytes for every 3 bytes of data
+        let max_size = match settings.binary {
+            true => MAX_MESSAGE_SIZE / 4 * 3,
+            false => MAX_MESSAGE_SIZE,
+        };
+
+        // fail early rather than once the writer closes the file. What was written so far is
+        // dropped, so that closing the file doesn't send a truncated message
+        if settings.write_mode == WriteMode::Message && end > max_size {
+            handle.write_buffer.clear();
+            return Err(anyhow!("message for {} can't be above {} bytes as per SQS limits", handle.queue_url, max_size)
+                .context(SQSError::MessageTooLarge));
+        }
+
+        // in lines mode the buffer only holds the lines not sent yet, so a write can't start past
+        // its end: the gap would have to be filled in first, whatever its size
+        if settings.write_mode == WriteMode::Lines && start > handle.write_buffer.len() {
+            return Err(anyhow!("data written to {} at offset {} would leave a gap in the line being written", handle.queue_url, offset)
+                .context(SQSError::InvalidInput));
+        }
+
+        if end > handle.write_buffer.len() {
+            handle.write_buffer.resize(end, 0);
+        }
+        handle.write_buffer[start..end].copy_from_slice(data);
+
+        if settings.write_mode == WriteMode::Lines {
+            let separator = separator(settings.delimiter);
+            let complete = handle.write_buffer.iter()
+                .rposition(|byte| *byte == separator)
+                .map_or(0, |position| position + 1);
+
+            // same as above, but for the line being written
+            if handle.write_buffer.len() - complete > max_size {
+                handle.write_buffer.truncate(complete);
+                return Err(anyhow!("line for {} can't be above {} bytes as per SQS limits", handle.queue_url, max_size)
+                    .context(SQSError::MessageTooLarge));
+            }
+
+            // no need to hold on to complete lines once there are enough of them for a batch
+            let lines = handle.write_buffer[..complete].iter()
+                .filter(|byte| **byte == separator)
+                .count();
+            if lines >= MAX_BATCH_SIZE || complete >= MAX_MESSAGE_SIZE {
+                let lines: Vec<u8> = handle.write_buffer.drain(..complete).collect();
+                handle.write_start += complete as u64;
+                self.send_lines(&mut handle, &lines, &settings)?;
+            }
         }
+
+        Ok(data.len() as u32)
     }
 
-    FileAttr {
-        ino: inode,
-        size,
-        blocks: size / blksize as u64,
-        atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-        mtime: UNIX_EPOCH,
-        ctime: UNIX_EPOCH,
-        crtime: UNIX_EPOCH,
-        kind,
-        perm,
-        nlink,
-        uid: unsafe { getuid() },
-        gid: unsafe { getgid() },
-        rdev: 0,
-        flags: 0,
-        blksize,
+    /// Write to a scheduled message, which is sent as a whole once due. It has to fit in a single
+    /// message unless the queue is in lines mode
+    fn write_scheduled(&self, spool: &Spool, queue_name: &str, name: &str, offset: u64, data: &[u8], append: bool) -> anyhow::Result<u32> {
+        let settings = self.cli_args.queue_settings(queue_name);
+        let max_size = match settings.binary {
+            true => MAX_MESSAGE_SIZE / 4 * 3,
+            false => MAX_MESSAGE_SIZE,
+        };
+
+        let end = match append {
+            true => spool.find(queue_name, name)?.map_or(0, |message| message.size),
+            false => offset,
+        } + data.len() as u64;
+        if settings.write_mode == WriteMode::Message && end > max_size as u64 {
+            return Err(anyhow!("message scheduled for {} can't be above {} bytes as per SQS limits", queue_name, max_size)
+                .context(SQSError::MessageTooLarge));
+        }
+
+        spool.write(queue_name, name, offset, data, append)?;
+        Ok(data.len() as u32)
+    }
+
+    /// Send what was written through this handle, if anything
+    pub fn flush(&self, fh: u64) -> anyhow::Result<()> {
+        match self.get_file_handler(fh) {
+            Some(handle) => self.send_written(&mut handle.state.lock().unwrap()),
+            None => Ok(()),
+        }
+    }
+
+    fn send_written(&self, handle: &mut FileHandleState) -> anyhow::Result<()> {
+        if handle.write_buffer.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::take(&mut handle.write_buffer);
+        handle.write_start += data.len() as u64;
+
+        let settings = self.cli_args.queue_settings(&handle.queue_name);
+        if settings.write_mode == WriteMode::Lines {
+            return self.send_lines(handle, &data, &settings);
+        }
+
+        // whether the message is text can only be told once it's complete, as a character may
+        // be split across two writes
+        let queue_url = handle.queue_url.clone();
+        build_message(data, settings.binary)
+            .and_then(|mut message| {
+                self.apply_options(handle, &mut message)?;
+                self.backend.send_message(queue_url.as_str(), &message)
+            })
+            .context(format!("Error while sending message to: {}", queue_url))?;
+        Ok(())
+    }
+
+    /// Apply the settings of the handle to a message about to be sent through it, including the
+    /// group and deduplication IDs required by FIFO queues
+    fn apply_options(&self, handle: &mut FileHandleState, message: &mut OutgoingMessage) -> anyhow::Result<()> {
+        message.attributes.extend(handle.options.attributes.clone());
+        message.delay_seconds = handle.options.delay_seconds;
+        if message.attributes.len() > MAX_MESSAGE_ATTRIBUTES {
+            return Err(anyhow!("messages can't have more than {} attributes", MAX_MESSAGE_ATTRIBUTES).context(SQSError::InvalidInput));
+        }
+
+        if !sqs::is_fifo_queue(&handle.queue_name) {
+            return Ok(());
+        }
+
+        let group_id = handle.options.group_id.clone()
+            .ok_or_else(|| anyhow!("messages sent to FIFO queues need a group ID, see {}", GROUP_ID_XATTR)
+                .context(SQSError::InvalidInput))?;
+
+        let sequence = handle.messages_sent;
+        handle.messages_sent += 1;
+
+        message.group_id = Some(group_id);
+        message.deduplication_id = match handle.options.deduplication_id.clone() {
+            // reusing the ID for the following messages would get them dropped
+            Some(deduplication_id) if sequence == 0 => Some(deduplication_id),
+            Some(deduplication_id) => Some(format!("{}-{}", deduplication_id, sequence)),
+            None if self.content_based_deduplication(handle)? => None,
+            // same as what SQS does with content-based deduplication
+            None => Some(format!("{:x}", Sha256::digest(message.body.as_bytes()))),
+        };
+        Ok(())
+    }
+
+    fn content_based_deduplication(&self, handle: &mut FileHandleState) -> anyhow::Result<bool> {
+        if let Some(content_based_deduplication) = handle.content_based_deduplication {
+            return Ok(content_based_deduplication);
+        }
+
+        let attributes = self.backend.get_queue_attributes(handle.queue_url.as_str())
+            .context(format!("Error while getting attributes of: {}", handle.queue_url))?;
+        let content_based_deduplication = attributes.get(&QueueAttributeName::ContentBasedDeduplication)
+            .is_some_and(|value| value == "true");
+
+        handle.content_based_deduplication = Some(content_based_deduplication);
+        Ok(content_based_deduplication)
+    }
+
+    /// Send each line of `data` as a message of its own, using as few requests as possible. Lines
+    /// that can't be sent don't hold up the other ones, they're logged and the first failure is
+    /// returned once all lines were dealt with.
+    fn send_lines(&self, handle: &mut FileHandleState, data: &[u8], settings: &QueueSettings) -> anyhow::Result<()> {
+        let queue_url = handle.queue_url.clone();
+        let queue_url = queue_url.as_str();
+        let mut errors = vec![];
+        let mut batch = vec![];
+        let mut batch_size = 0;
+
+        // SQS doesn't take empty messages
+        let lines = data.split(|byte| *byte == separator(settings.delimiter))
+            .filter(|line| !line.is_empty());
+
+        for line in lines {
+            let message = build_message(line.to_vec(), settings.binary)
+                .and_then(|mut message| {
+                    self.apply_options(handle, &mut message)?;
+                    Ok(message)
+                });
+
+            let message = match message {
+                Ok(message) => message,
+                Err(error) => {
+                    errors.push((1, error));
+                    continue;
+                }
+            };
+
+            if batch.len() == MAX_BATCH_SIZE || batch_size + message.size() > MAX_MESSAGE_SIZE {
+                errors.extend(self.send_batch(queue_url, std::mem::take(&mut batch)));
+                batch_size = 0;
+            }
+            batch_size += message.size();
+            batch.push(message);
+        }
+
+        if !batch.is_empty() {
+            errors.extend(self.send_batch(queue_url, batch));
+        }
+
+        for (_, error) in &errors {
+            warn!("failed to send line(s) to {}: {:#}", queue_url, error);
+        }
+
+        let failed: usize = errors.iter().map(|(lines, _)| lines).sum();
+        match errors.into_iter().next() {
+            Some((_, error)) => Err(error.context(format!("{} line(s) couldn't be sent to: {}", failed, queue_url))),
+            None => Ok(()),
+        }
+    }
+
+    /// Send a batch of messages, retrying the ones SQS failed to send through no fault of their
+    /// own. Returns the errors that are left along with the number of messages they concern.
+    fn send_batch(&self, queue_url: &str, mut messages: Vec<OutgoingMessage>) -> Vec<(usize, anyhow::Error)> {
+        let mut errors = vec![];
+
+        for attempt in 1..=self.cli_args.max_attempts {
+            let failures = match self.backend.send_message_batch(queue_url, &messages) {
+                Ok(failures) => failures,
+                Err(error) => {
+                    errors.push((messages.len(), error));
+                    break;
+                }
+            };
+
+            let (retryable, permanent): (Vec<_>, Vec<_>) = failures.into_iter()
+                .partition(|failure| !failure.sender_fault && attempt < self.cli_args.max_attempts);
+            errors.extend(permanent.into_iter().map(|failure| (1, failure.error)));
+
+            if retryable.is_empty() {
+                break;
+            }
+
+            warn!("retrying {} message(s) SQS failed to send to {} (attempt {})", retryable.len(), queue_url, attempt + 1);
+            thread::sleep(BATCH_RETRY_DELAY * attempt);
+
+            let retries = retryable.iter()
+                .map(|failure| std::mem::take(&mut messages[failure.index]))
+                .collect();
+            messages = retries;
+        }
+
+        errors
+    }
+
+    pub fn create_file_handler(&self, mode: u16, metadata: &Metadata, pid: u32) -> u64 {
+        let settings = self.cli_args.queue_settings(&metadata.queue_name);
+        let mut options = self.message_options.lock().unwrap()
+            .get(&metadata.queue_url)
+            .cloned()
+            .unwrap_or_default();
+        // options given in the path take precedence, being the most specific ones
+        options.group_id = metadata.path_options.group_id.clone()
+            .or(options.group_id)
+            .or(settings.message_group_id);
+        // FIFO queues can only delay all of their messages, so the delay set for the mount or the
+        // queue only applies to standard queues
+        options.delay_seconds = match sqs::is_fifo_queue(&metadata.queue_name) {
+            true => None,
+            false => metadata.path_options.delay_seconds
+                .or(options.delay_seconds)
+                .or(settings.delay_seconds),
+        };
+
+        // peek never deletes anything, whatever the read mode
+        let read_mode = match metadata.node {
+            Node::Peek => ReadMode::Peek,
+            _ => self.cli_args.read_mode,
+        };
+
+        let fh = self.next_file_handle.fetch_add(1, Ordering::SeqCst);
+        self.fh_map.lock().unwrap().insert(fh, Arc::new(FileHandle {
+            mode,
+            ino: metadata.file_attr.ino,
+            node: metadata.node.clone(),
+            pid,
+            state: Mutex::new(FileHandleState {
+                queue_name: metadata.queue_name.clone(),
+                queue_url: metadata.queue_url.clone(),
+                read_mode,
+                inflight: vec![],
+                buffer: vec![],
+                buffer_start: 0,
+                buffered_messages: vec![],
+                last_message: None,
+                received: false,
+                fully_read: false,
+                write_buffer: vec![],
+                write_start: 0,
+                options,
+                messages_sent: 0,
+                content_based_deduplication: None,
+            }),
+        }));
+        fh
+    }
+
+    /// Set one of the settings of the messages written to a queue, or remove it when `value` is
+    /// None. The setting applies to the files the calling process has open on the queue, or to
+    /// the files opened from now on if there are none, e.g. when using setfattr.
+    pub fn set_xattr(&self, metadata: &Metadata, pid: u32, name: &str, value: Option<String>) -> anyhow::Result<()> {
+        if !metadata.node.is_queue() {
+            return Err(anyhow!("extended attributes can only be set on queues").context(SQSError::Unsupported));
+        }
+        check_own_delay(&metadata.queue_name, name == DELAY_SECONDS_XATTR && value.is_some())?;
+
+        let handles = self.caller_handles(metadata, pid);
+        if handles.is_empty() {
+            // a deduplication ID shared by all messages would get all but the first one dropped
+            if name == DEDUPLICATION_ID_XATTR {
+                return Err(anyhow!("{} can only be set on a queue being written to", name)
+                    .context(SQSError::InvalidInput));
+            }
+            let mut message_options = self.message_options.lock().unwrap();
+            return set_message_option(message_options.entry(metadata.queue_url.clone()).or_default(), name, value);
+        }
+
+        for handle in handles {
+            set_message_option(&mut handle.state.lock().unwrap().options, name, value.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Value of an extended attribute of the calling process, see `xattrs`
+    pub fn get_xattr(&self, metadata: &Metadata, pid: u32, name: &str) -> Option<Vec<u8>> {
+        self.xattrs(metadata, pid).remove(name)
+    }
+
+    /// Names of the extended attributes of the calling process, see `xattrs`
+    pub fn list_xattr(&self, metadata: &Metadata, pid: u32) -> Vec<String> {
+        self.xattrs(metadata, pid).into_keys().collect()
+    }
+
+    /// Extended attributes as the calling process sees them: those describing the last message it
+    /// read, see `message_xattrs`, along with the settings of the messages it writes, which take
+    /// precedence so that what's set through setxattr reads back as is
+    fn xattrs(&self, metadata: &Metadata, pid: u32) -> BTreeMap<String, Vec<u8>> {
+        let mut xattrs = self.last_message(metadata, pid)
+            .map(|msg| message_xattrs(&msg))
+            .unwrap_or_default();
+        if let Some(options) = self.message_options(metadata, pid) {
+            xattrs.extend(option_xattrs(&options));
+        }
+        xattrs
+    }
+
+    /// Last message read through the files the calling process has open on the queue, or through
+    /// any file if there are none, e.g. when using getfattr after cat
+    fn last_message(&self, metadata: &Metadata, pid: u32) -> Option<Message> {
+        // files of an inflight directory stand for a single message
+        if let Node::InflightMessage(message_id) = &metadata.node {
+            return self.received_message(&metadata.queue_url, message_id);
+        }
+        if !metadata.node.is_queue() {
+            return None;
+        }
+
+        let handles = self.caller_handles(metadata, pid);
+        if handles.is_empty() {
+            return self.last_messages.lock().unwrap().get(&metadata.queue_url).cloned();
+        }
+        handles.into_iter()
+            .find_map(|handle| handle.state.lock().unwrap().last_message.clone())
+    }
+
+    /// Settings of the messages written through the files the calling process has open on the
+    /// queue, or through the files opened from now on if there are none, see `set_xattr`
+    fn message_options(&self, metadata: &Metadata, pid: u32) -> Option<MessageOptions> {
+        if !metadata.node.is_queue() {
+            return None;
+        }
+
+        match self.caller_handles(metadata, pid).first() {
+            Some(handle) => Some(handle.state.lock().unwrap().options.clone()),
+            None => self.message_options.lock().unwrap().get(&metadata.queue_url).cloned(),
+        }
+    }
+
+    /// Files the calling process has open on the file at hand, the most recently opened first
+    fn caller_handles(&self, metadata: &Metadata, pid: u32) -> Vec<Arc<FileHandle>> {
+        let mut handles: Vec<(u64, Arc<FileHandle>)> = self.fh_map.lock().unwrap().iter()
+            .filter(|(_, handle)| handle.ino == metadata.file_attr.ino && handle.pid == pid)
+            .map(|(fh, handle)| (*fh, handle.clone()))
+            .collect();
+
+        handles.sort_by_key(|(fh, _)| std::cmp::Reverse(*fh));
+        handles.into_iter().map(|(_, handle)| handle).collect()
+    }
+
+    /// Create a file or directory: schedule a message for the queue of a `scheduled/<queue>`
+    /// directory, due at the time it's named after, or create a queue in the top level directory
+    /// (a file in the flat layout, a directory otherwise). None means nothing of that kind can be
+    /// created in that directory
+    pub fn create(&self, parent: &Metadata, name: &str, kind: FileType) -> anyhow::Result<Option<Metadata>> {
+        let queue_kind = match self.cli_args.layout {
+            Layout::Flat => FileType::RegularFile,
+            Layout::Directories => FileType::Directory,
+        };
+
+        match (&parent.node, &self.spool) {
+            (Node::ScheduledQueue, Some(spool)) if kind == FileType::RegularFile => {
+                let message = spool.create(&parent.queue_name, name)?;
+                Ok(Some(self.scheduled_metadata(parent, &message)))
+            }
+            (Node::Root, _) if kind == queue_kind => self.create_queue(name).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn create_queue(&self, queue_name: &str) -> anyhow::Result<Metadata> {
+        if !self.cli_args.allow_queue_admin {
+            return Err(anyhow!("queues can only be created with --allow-queue-admin").context(SQSError::NotPermitted));
+        }
+        if self.cli_args.layout == Layout::Flat && self.spool.is_some() && queue_name == SCHEDULED_DIR {
+            return Err(anyhow!("{} is where messages are scheduled", queue_name).context(SQSError::InvalidInput));
+        }
+
+        let queue_url = self.backend.create_queue(queue_name)
+            .context(format!("Error while creating queue: {}", queue_name))?;
+        info!("created queue: {}", queue_url);
+
+        // listing queues may take a while to show the new one, so don't wait for the next refresh
+        // and keep adding it until it's listed
+        let mut superblock = self.superblock.write().unwrap();
+        let ino = self.add_queue(&mut superblock, queue_name, &queue_url);
+        self.queue_changes.lock().unwrap().insert(queue_url, QueueChange::Created(queue_name.to_string()));
+        Ok(superblock.inodes[&ino].clone())
+    }
+
+    fn delete_queue(&self, metadata: &Metadata) -> anyhow::Result<()> {
+        if !self.cli_args.allow_queue_admin {
+            return Err(anyhow!("queues can only be deleted with --allow-queue-admin").context(SQSError::NotPermitted));
+        }
+
+        self.backend.delete_queue(&metadata.queue_url)
+            .context(format!("Error while deleting queue: {}", metadata.queue_url))?;
+        info!("deleted queue: {}", metadata.queue_url);
+
+        // same as creating a queue, listing queues may still show it for a while
+        {
+            let mut superblock = self.superblock.write().unwrap();
+            superblock.remove_queue(&metadata.queue_url);
+            self.queue_changes.lock().unwrap().insert(metadata.queue_url.clone(), QueueChange::Deleted);
+        }
+        self.message_options.lock().unwrap().remove(&metadata.queue_url);
+        self.last_messages.lock().unwrap().remove(&metadata.queue_url);
+        self.received_messages.lock().unwrap().remove(&metadata.queue_url);
+        Ok(())
+    }
+
+    /// Change the size of a scheduled message, e.g. when it's opened with O_TRUNC
+    pub fn truncate_scheduled(&self, metadata: &Metadata, size: u64) -> anyhow::Result<()> {
+        match (&metadata.node, &self.spool) {
+            (Node::ScheduledMessage(name), Some(spool)) => spool.truncate(&metadata.queue_name, name, size),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove a file or directory: cancel a scheduled message, delete a message received into an
+    /// `inflight` directory, or delete a queue
+    pub fn remove(&self, metadata: &Metadata) -> anyhow::Result<()> {
+        match (&metadata.node, &self.spool) {
+            (Node::ScheduledMessage(name), Some(spool)) => {
+                spool.remove(&metadata.queue_name, name)?;
+                self.dynamic_inodes.lock().unwrap().remove(&metadata.path);
+                Ok(())
+            }
+            (Node::InflightMessage(message_id), _) => self.delete_received(metadata, message_id),
+            // queues looked up with path options, e.g. `jobs@delay=300`, are aliases
+            (Node::Queue | Node::QueueDir, _) if metadata.path == metadata.queue_name => self.delete_queue(metadata),
+            _ => Err(anyhow!("{} can't be removed", metadata.path).context(SQSError::Unsupported)),
+        }
+    }
+
+    fn delete_received(&self, metadata: &Metadata, message_id: &str) -> anyhow::Result<()> {
+        let receipt_handle = self.received_message(&metadata.queue_url, message_id)
+            .and_then(|msg| msg.receipt_handle)
+            .ok_or_else(|| anyhow!("message {} is visible again, it can't be deleted anymore", message_id)
+                .context(SQSError::InvalidInput))?;
+
+        self.backend.delete_message(&metadata.queue_url, &receipt_handle)
+            .context(format!("Error while deleting message with receipt handle: {}", receipt_handle))?;
+
+        if let Some(received_messages) = self.received_messages.lock().unwrap().get_mut(&metadata.queue_url) {
+            received_messages.remove(message_id);
+        }
+        self.dynamic_inodes.lock().unwrap().remove(&metadata.path);
+        Ok(())
+    }
+
+    /// Send scheduled messages as they fall due, on a thread of their own. Does nothing unless
+    /// there's a spool
+    pub fn start_scheduler(self: &Arc<Self>) -> anyhow::Result<()> {
+        if self.spool.is_none() {
+            return Ok(());
+        }
+
+        let sqs_fs = self.clone();
+        thread::Builder::new()
+            .name("sqsfs-scheduler".to_string())
+            .spawn(move || sqs_fs.run_scheduler())?;
+        Ok(())
+    }
+
+    fn run_scheduler(&self) {
+        let Some(spool) = &self.spool else {
+            return;
+        };
+
+        loop {
+            let wait = self.send_scheduled(spool);
+            spool.wait(wait);
+        }
+    }
+
+    /// Send the scheduled messages that are due, returning how long to wait until looking again
+    fn send_scheduled(&self, spool: &Spool) -> Duration {
+        let queues = match spool.queues() {
+            Ok(queues) => queues,
+            Err(error) => {
+                warn!("{:#}", error);
+                return SCHEDULER_RETRY_DELAY;
+            }
+        };
+
+        let mut wait = SCHEDULER_MAX_WAIT;
+        for queue_name in queues {
+            let messages = match spool.list(&queue_name) {
+                Ok(messages) => messages,
+                Err(error) => {
+                    warn!("{:#}", error);
+                    wait = wait.min(SCHEDULER_RETRY_DELAY);
+                    continue;
+                }
+            };
+
+            for message in messages {
+                // messages are sorted, so none of the following ones are due either
+                if let Ok(remaining) = message.due.duration_since(SystemTime::now()) {
+                    wait = wait.min(remaining);
+                    break;
+                }
+
+                match self.send_scheduled_message(spool, &queue_name, &message) {
+                    Ok(true) => info!("sent message scheduled for {} to {}", message.name, queue_name),
+                    // release_file_handler wakes the scheduler up once the writer is done
+                    Ok(false) => debug!("message scheduled for {} on {} is still being written", message.name, queue_name),
+                    Err(error) => {
+                        warn!("{:#}", error.context(format!("Error while sending message scheduled for {} to {}", message.name, queue_name)));
+                        wait = wait.min(SCHEDULER_RETRY_DELAY);
+                    }
+                }
+            }
+        }
+        wait
+    }
+
+    /// Send a scheduled message as if it was written to the queue now, so that the settings of
+    /// the queue apply to it, e.g. lines mode. Returns false if the message is still open
+    fn send_scheduled_message(&self, spool: &Spool, queue_name: &str, message: &ScheduledMessage) -> anyhow::Result<bool> {
+        self.refresh()?;
+        let (queue, scheduled_dir) = {
+            let superblock = self.superblock.read().unwrap();
+            let find = |is_wanted: fn(&Node) -> bool| superblock.inodes.values()
+                .find(|metadata| metadata.queue_name == queue_name && is_wanted(&metadata.node))
+                .cloned();
+            (find(|node| matches!(node, Node::Queue | Node::Send)), find(|node| *node == Node::ScheduledQueue))
+        };
+        let (Some(queue), Some(scheduled_dir)) = (queue, scheduled_dir) else {
+            return Err(anyhow!("queue {} doesn't exist", queue_name).context(SQSError::QueueDoesNotExist));
+        };
+
+        // a message that was never looked up can't be open
+        let path = child_path(&scheduled_dir, &message.name);
+        let ino = self.dynamic_inodes.lock().unwrap().inodes.get(&path).copied();
+        if ino.is_some_and(|ino| self.fh_map.lock().unwrap().values().any(|handle| handle.ino == ino)) {
+            return Ok(false);
+        }
+
+        let data = spool.read(queue_name, &message.name, 0, message.size as u32)?;
+        let fh = self.create_file_handler(libc::W_OK as u16, &queue, 0);
+        let written = self.write(fh, 0, &data, false);
+        let released = self.release_file_handler(fh);
+        written?;
+        released?;
+
+        spool.remove(queue_name, &message.name)?;
+        self.dynamic_inodes.lock().unwrap().remove(&path);
+        Ok(true)
+    }
+
+    fn get_file_handler(&self, fh: u64) -> Option<Arc<FileHandle>> {
+        self.fh_map.lock().unwrap().get(&fh).cloned()
+    }
+
+    pub fn check_file_handler_mode(&self, fh: u64, mode: u16) -> bool {
+        match self.get_file_handler(fh) {
+            Some(handle) => handle.mode & mode > 0,
+            None => false,
+        }
+    }
+
+    /// Delete the messages handed out through this handle so far
+    pub fn acknowledge(&self, fh: u64) -> anyhow::Result<()> {
+        let Some(handle) = self.get_file_handler(fh) else {
+            return Ok(());
+        };
+        let mut handle = handle.state.lock().unwrap();
+
+        // peeking never deletes anything
+        if handle.read_mode == ReadMode::Peek {
+            return Ok(());
+        }
+        self.delete_inflight_until(&mut handle, u64::MAX)
+    }
+
+    /// Delete in-flight messages whose data ends at or before `offset`
+    fn delete_inflight_until(&self, handle: &mut FileHandleState, offset: u64) -> anyhow::Result<()> {
+        let count = handle.inflight.iter()
+            .take_while(|inflight| inflight.end <= offset)
+            .count();
+        let receipt_handles: Vec<String> = handle.inflight[..count].iter()
+            .map(|inflight| inflight.receipt_handle.clone())
+            .collect();
+
+        self.delete_messages(handle.queue_url.as_str(), &receipt_handles)?;
+        handle.inflight.drain(..count);
+        Ok(())
+    }
+
+    pub fn release_file_handler(&self, fh: u64) -> anyhow::Result<()> {
+        let handle = match self.fh_map.lock().unwrap().remove(&fh) {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+
+        // the scheduler leaves messages alone while they're being written
+        if let (Node::ScheduledMessage(_), Some(spool)) = (&handle.node, &self.spool) {
+            spool.notify();
+            return Ok(());
+        }
+        let mut handle = handle.state.lock().unwrap();
+
+        // close() flushes the handle already, unless the file was never closed properly
+        let sent = self.send_written(&mut handle);
+        self.release_inflight(&mut handle)?;
+        sent
+    }
+
+    fn release_inflight(&self, handle: &mut FileHandleState) -> anyhow::Result<()> {
+        if handle.inflight.is_empty() {
+            return Ok(());
+        }
+
+        // messages peeked at from a stream were kept invisible so that the stream doesn't
+        // show them over and over again, they can go back to the queue now
+        if handle.read_mode == ReadMode::Peek {
+            for inflight in std::mem::take(&mut handle.inflight) {
+                self.backend.change_message_visibility(handle.queue_url.as_str(), &inflight.receipt_handle, 0)
+                    .context(format!("Error while releasing message with receipt handle: {}", inflight.receipt_handle))?;
+            }
+            return Ok(());
+        }
+
+        // a reader that didn't get to the end of the message was most likely interrupted, so
+        // leave the message to reappear once its visibility timeout expires
+        if !handle.fully_read {
+            info!(
+                "{} message(s) from {} weren't fully read, leaving them to be received again",
+                handle.inflight.len(),
+                handle.queue_url
+            );
+            return Ok(());
+        }
+
+        self.delete_inflight_until(handle, u64::MAX)
+    }
+}
+
+/// Path of an entry from the top level directory
+fn child_path(parent: &Metadata, name: &str) -> String {
+    match parent.path.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", parent.path, name),
+    }
+}
+
+/// Name of the dead-letter queue set in the redrive policy of a queue, e.g.
+/// `{"deadLetterTargetArn": "arn:aws:sqs:us-east-1:123456789012:jobs-dlq", "maxReceiveCount": 5}`
+fn dead_letter_queue_name(redrive_policy: &str) -> Option<String> {
+    let redrive_policy: serde_json::Value = serde_json::from_str(redrive_policy).ok()?;
+    let arn = redrive_policy.get("deadLetterTargetArn")?.as_str()?;
+    arn.rsplit(':').next().map(str::to_string)
+}
+
+/// Dead-letter queues are linked to from the directory of the queue they belong to
+fn dlq_link_target(dlq_name: &str) -> String {
+    format!("../{}", dlq_name)
+}
+
+/// Byte that separates messages from each other
+fn separator(delimiter: Delimiter) -> u8 {
+    match delimiter {
+        Delimiter::Newline | Delimiter::JsonLines => b'\n',
+        Delimiter::Nul => b'\0',
+    }
+}
+
+fn set_message_option(options: &mut MessageOptions, name: &str, value: Option<String>) -> anyhow::Result<()> {
+    if let Some(attribute_name) = name.strip_prefix(ATTRIBUTE_XATTR_PREFIX) {
+        if attribute_name.is_empty() || attribute_name == ENCODING_ATTRIBUTE {
+            return Err(anyhow!("invalid message attribute name: {}", attribute_name).context(SQSError::InvalidInput));
+        }
+
+        match value {
+            Some(value) => {
+                let value = parse_attribute_value(&value)
+                    .context(SQSError::InvalidInput)
+                    .context(format!("Invalid value for message attribute: {}", attribute_name))?;
+                options.attributes.insert(attribute_name.to_string(), value);
+            }
+            None => {
+                options.attributes.remove(attribute_name);
+            }
+        }
+        return Ok(());
+    }
+
+    match name {
+        GROUP_ID_XATTR => options.group_id = value,
+        DEDUPLICATION_ID_XATTR => options.deduplication_id = value,
+        DELAY_SECONDS_XATTR => options.delay_seconds = value.as_deref().map(parse_delay_seconds).transpose()?,
+        _ => return Err(anyhow!("unknown extended attribute: {}", name).context(SQSError::Unsupported)),
+    }
+    Ok(())
+}
+
+/// Fail if a message sent to a FIFO queue is given a delay of its own, which SQS doesn't allow
+fn check_own_delay(queue_name: &str, delayed: bool) -> anyhow::Result<()> {
+    if delayed && sqs::is_fifo_queue(queue_name) {
+        return Err(anyhow!("messages sent to FIFO queues can't have their own delay, see the DelaySeconds queue attribute")
+            .context(SQSError::InvalidInput));
+    }
+    Ok(())
+}
+
+fn parse_delay_seconds(value: &str) -> anyhow::Result<i32> {
+    match value.parse() {
+        Ok(delay_seconds) if (0..=MAX_DELAY_SECONDS).contains(&delay_seconds) => Ok(delay_seconds),
+        _ => Err(anyhow!("delay must be between 0 and {} seconds, got: {}", MAX_DELAY_SECONDS, value)
+            .context(SQSError::InvalidInput)),
+    }
+}
+
+/// Split the value of a `user.sqs.attr.` xattr into its data type and its data, if it starts with
+/// a data type
+fn split_data_type(value: &str) -> Option<(&str, &str)> {
+    // custom types, e.g. Number.float, are made of a data type followed by a label
+    value.split_once(':')
+        .filter(|(data_type, _)| ATTRIBUTE_DATA_TYPES.contains(&data_type.split('.').next().unwrap_or_default()))
+}
+
+/// Parse the value of a `user.sqs.attr.` xattr. Values may start with their SQS data type, e.g.
+/// `Number:42` or `Binary:<base64>`, and are strings otherwise
+fn parse_attribute_value(value: &str) -> anyhow::Result<MessageAttributeValue> {
+    let (data_type, data) = split_data_type(value).unwrap_or(("String", value));
+
+    let builder = MessageAttributeValue::builder().data_type(data_type);
+    let builder = match data_type.split('.').next().unwrap_or_default() {
+        "Binary" => builder.binary_value(Blob::new(BASE64_STANDARD.decode(data)?)),
+        "Number" => {
+            data.parse::<f64>()?;
+            builder.string_value(data)
+        }
+        _ => builder.string_value(data),
+    };
+    Ok(builder.build()?)
+}
+
+/// Format a message attribute the way `parse_attribute_value` reads it, so that it can be set again
+/// on another message as is
+fn format_attribute_value(value: &MessageAttributeValue) -> String {
+    let data = match value.binary_value() {
+        Some(binary_value) => BASE64_STANDARD.encode(binary_value),
+        None => value.string_value().unwrap_or_default().to_string(),
+    };
+
+    // strings don't need their type, unless they could be taken for another one
+    if value.data_type() == "String" && split_data_type(&data).is_none() {
+        data
+    } else {
+        format!("{}:{}", value.data_type(), data)
+    }
+}
+
+/// Extended attributes standing for the settings of the messages written to a queue, the way
+/// `set_message_option` takes them
+fn option_xattrs(options: &MessageOptions) -> BTreeMap<String, Vec<u8>> {
+    let mut xattrs = BTreeMap::new();
+    let settings = [
+        (GROUP_ID_XATTR, options.group_id.clone()),
+        (DEDUPLICATION_ID_XATTR, options.deduplication_id.clone()),
+        (DELAY_SECONDS_XATTR, options.delay_seconds.map(|delay_seconds| delay_seconds.to_string())),
+    ];
+    for (name, value) in settings {
+        if let Some(value) = value {
+            xattrs.insert(name.to_string(), value.into_bytes());
+        }
+    }
+
+    for (name, value) in &options.attributes {
+        xattrs.insert(format!("{}{}", ATTRIBUTE_XATTR_PREFIX, name), format_attribute_value(value).into_bytes());
+    }
+    xattrs
+}
+
+/// Extended attributes describing a received message: its ID, receive count, sent timestamp and
+/// each of its message attributes, as `user.sqs.attr.<name>`
+fn message_xattrs(msg: &Message) -> BTreeMap<String, Vec<u8>> {
+    let mut xattrs = BTreeMap::new();
+    if let Some(message_id) = msg.message_id() {
+        xattrs.insert(MESSAGE_ID_XATTR.to_string(), message_id.as_bytes().to_vec());
+    }
+
+    let system_attributes = [
+        (RECEIVE_COUNT_XATTR, MessageSystemAttributeName::ApproximateReceiveCount),
+        (SENT_TIMESTAMP_XATTR, MessageSystemAttributeName::SentTimestamp),
+    ];
+    for (name, attribute) in system_attributes {
+        if let Some(value) = msg.attributes().and_then(|attributes| attributes.get(&attribute)) {
+            xattrs.insert(name.to_string(), value.as_bytes().to_vec());
+        }
+    }
+
+    for (name, value) in msg.message_attributes().into_iter().flatten() {
+        xattrs.insert(format!("{}{}", ATTRIBUTE_XATTR_PREFIX, name), format_attribute_value(value).into_bytes());
+    }
+    xattrs
+}
+
+/// Turn data written to a queue into a message. SQS only takes UTF-8 text without control
+/// characters, so in binary mode anything else is base64-encoded and tagged as such, while text is
+/// sent untouched
+fn build_message(data: Vec<u8>, binary: bool) -> anyhow::Result<OutgoingMessage> {
+    let text = match String::from_utf8(data) {
+        Ok(body) if is_valid_body(&body) => Ok(body),
+        Ok(body) => Err(body.into_bytes()),
+        Err(error) => Err(error.into_bytes()),
+    };
+
+    let message = match text {
+        Ok(body) => OutgoingMessage::new(body),
+        Err(data) if binary => {
+            let mut message = OutgoingMessage::new(BASE64_STANDARD.encode(data));
+            message.attributes.insert(ENCODING_ATTRIBUTE.to_string(), MessageAttributeValue::builder()
+                .data_type("String")
+                .string_value(BASE64_ENCODING)
+                .build()?);
+            message
+        }
+        Err(_) => return Err(anyhow!("data isn't valid UTF-8 or holds control characters, see binary mode")
+            .context(SQSError::InvalidInput)),
+    };
+
+    if message.size() > MAX_MESSAGE_SIZE {
+        return Err(anyhow!("message length can't be above 256kb as per SQS limits")
+            .context(SQSError::MessageTooLarge));
+    }
+    Ok(message)
+}
+
+fn is_base64_encoded(msg: &Message) -> bool {
+    msg.message_attributes()
+        .and_then(|attributes| attributes.get(ENCODING_ATTRIBUTE))
+        .and_then(|encoding| encoding.string_value())
+        == Some(BASE64_ENCODING)
+}
+
+/// Body of a received message, decoded in binary mode if it was encoded when sent
+fn message_body(msg: &Message, binary: bool) -> Vec<u8> {
+    let body = msg.body().unwrap_or_default();
+
+    if binary && is_base64_encoded(msg) {
+        match BASE64_STANDARD.decode(body) {
+            Ok(data) => return data,
+            Err(error) => warn!(
+                "message {} is tagged as base64 but can't be decoded, serving it as is: {}",
+                msg.message_id().unwrap_or_default(),
+                error
+            ),
+        }
+    }
+
+    body.as_bytes().to_vec()
+}
+
+/// Append a message to the data served to the reader. Messages received together are
+/// separated by the delimiter, while in a stream each message is terminated by it as the next
+/// one may never come. JSON Lines are always terminated by a newline.
+fn append_message(buffer: &mut Vec<u8>, msg: &Message, settings: &QueueSettings) {
+    let (delimiter, stream) = (settings.delimiter, settings.stream);
+
+    // JSON can't hold binary data, so encoded messages are left as they are
+    if delimiter == Delimiter::JsonLines {
+        let mut line = json!({
+            "message_id": msg.message_id(),
+            "body": msg.body().unwrap_or_default(),
+        });
+        if is_base64_encoded(msg) {
+            line["encoding"] = json!(BASE64_ENCODING);
+        }
+        buffer.extend_from_slice(line.to_string().as_bytes());
+        buffer.push(b'\n');
+        return;
+    }
+    let separator = separator(delimiter);
+    let body = message_body(msg, settings.binary);
+
+    // messages written with echo already carry their newline
+    let terminated = |buffer: &Vec<u8>| delimiter == Delimiter::Newline && buffer.ends_with(b"\n");
+
+    if !stream && !buffer.is_empty() && !terminated(buffer) {
+        buffer.push(separator);
+    }
+    buffer.extend_from_slice(&body);
+    if stream && !terminated(buffer) {
+        buffer.push(separator);
+    }
+}
+
+/// fuser doesn't pass FUSE_INTERRUPT requests on, so check whether the reader has a signal
+/// pending (e.g. Ctrl+C) or is gone altogether instead
+fn reader_interrupted(pid: u32) -> bool {
+    match Process::new(pid as i32).and_then(|process| process.status()) {
+        Ok(status) => status.sigpnd != 0 || status.shdpnd != 0,
+        Err(_) => true,
+    }
+}
+
+fn build_fileattr(inode: u64, kind: FileType) -> FileAttr {
+    let size: u64;
+    let perm: u16;
+    let nlink: u32;
+    let blksize: u32 = 512;
+
+    match kind {
+        FileType::Directory => {
+            size = 0;
+            perm = 0o755;
+            nlink = 2;
+        }
+        _ => {
+            size = 1024 * 1024;
+            perm = 0o644;
+            nlink = 1;
+        }
+    }
+
+    FileAttr {
+        ino: inode,
+        size,
+        blocks: size / blksize as u64,
+        atime: UNIX_EPOCH, // 1970-01-01 00:00:00
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind,
+        perm,
+        nlink,
+        uid: unsafe { getuid() },
+        gid: unsafe { getgid() },
+        rdev: 0,
+        flags: 0,
+        blksize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::memory::InMemoryBackend;
+
+    const READ: u16 = libc::R_OK as u16;
+    const WRITE: u16 = libc::W_OK as u16;
+
+    /// Filesystem serving `queue_names` from memory, mounted with `args`
+    fn mount(args: &[&str], queue_names: &[&str]) -> SQSFileSystem {
+        let cli_args = CliArgs::parse_from(["mountpoint-sqs", "/mnt"].iter().chain(args));
+        let queue_names: Vec<String> = queue_names.iter().map(|name| name.to_string()).collect();
+        SQSFileSystem::new(cli_args, Box::new(InMemoryBackend::new(&queue_names))).unwrap()
+    }
+
+    /// Same as `mount`, but received messages become visible again as soon as they are released
+    fn mount_without_visibility_timeout(args: &[&str], queue_names: &[&str]) -> SQSFileSystem {
+        let cli_args = CliArgs::parse_from(["mountpoint-sqs", "/mnt"].iter().chain(args));
+        let queue_names: Vec<String> = queue_names.iter().map(|name| name.to_string()).collect();
+        let backend = InMemoryBackend::new(&queue_names).with_visibility_timeout(Duration::ZERO);
+        SQSFileSystem::new(cli_args, Box::new(backend)).unwrap()
+    }
+
+    fn lookup(sqs_fs: &SQSFileSystem, path: &str) -> Metadata {
+        sqs_fs.find_by_path(path).unwrap().unwrap()
+    }
+
+    fn pid() -> u32 {
+        std::process::id()
+    }
+
+    /// Write `data` to the queue `name` in a single write, then close it
+    fn write_file(sqs_fs: &SQSFileSystem, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let fh = sqs_fs.create_file_handler(WRITE, &lookup(sqs_fs, name), pid());
+        let written = sqs_fs.write(fh, 0, data, false)
+            .and_then(|_| sqs_fs.flush(fh));
+        sqs_fs.release_file_handler(fh)?;
+        written
+    }
+
+    /// Read the queue `name` one message at a time until it's empty
+    fn read_messages(sqs_fs: &SQSFileSystem, name: &str) -> Vec<Vec<u8>> {
+        std::iter::repeat_with(|| read_file(sqs_fs, name))
+            .take_while(|message| !message.is_empty())
+            .collect()
+    }
+
+    /// Number of visible and in-flight messages of a queue
+    fn count(sqs_fs: &SQSFileSystem, queue_name: &str) -> (u64, u64) {
+        let attributes = sqs_fs.backend.get_queue_attributes(&lookup(sqs_fs, queue_name).queue_url).unwrap();
+        let count = |name: QueueAttributeName| attributes[&name].parse().unwrap();
+        (count(QueueAttributeName::ApproximateNumberOfMessages), count(QueueAttributeName::ApproximateNumberOfMessagesNotVisible))
+    }
+
+    /// Read from `fh` until EOF, `size` bytes at a time
+    fn read_to_end(sqs_fs: &SQSFileSystem, fh: u64, metadata: &Metadata, size: u32) -> Vec<u8> {
+        let mut data = vec![];
+        loop {
+            let chunk = sqs_fs.read(fh, metadata, data.len() as i64, size, pid()).unwrap();
+            if chunk.is_empty() {
+                return data;
+            }
+            data.extend(chunk);
+        }
+    }
+
+    /// Read the queue `name` to the end through a new handle, then close it
+    fn read_file(sqs_fs: &SQSFileSystem, name: &str) -> Vec<u8> {
+        let metadata = lookup(sqs_fs, name);
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        let data = read_to_end(sqs_fs, fh, &metadata, 4096);
+        sqs_fs.release_file_handler(fh).unwrap();
+        data
+    }
+
+    #[test]
+    fn queues_are_listed_as_files() {
+        let sqs_fs = mount(&[], &["jobs", "events"]);
+
+        let files = sqs_fs.list_files(ROOT_INODE).unwrap().unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["events", "jobs"]);
+        assert!(sqs_fs.find_by_path("unknown").unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_consume_messages() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello");
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"");
+    }
+
+    #[test]
+    fn writes_are_sent_as_a_single_message_on_flush() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        let fh = sqs_fs.create_file_handler(WRITE, &lookup(&sqs_fs, "jobs"), pid());
+
+        sqs_fs.write(fh, 5, b" world", false).unwrap();
+        sqs_fs.write(fh, 0, b"hello", false).unwrap();
+        sqs_fs.write(fh, 0, b"!", true).unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"");
+
+        sqs_fs.flush(fh).unwrap();
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello world!");
+    }
+
+    #[test]
+    fn messages_above_the_size_limit_are_refused_as_they_are_written() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        let fh = sqs_fs.create_file_handler(WRITE, &lookup(&sqs_fs, "jobs"), pid());
+
+        sqs_fs.write(fh, 0, b"hello", false).unwrap();
+        let error = sqs_fs.write(fh, MAX_MESSAGE_SIZE as i64, b"!", false).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EFBIG);
+
+        // nothing is sent rather than a truncated message
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"");
+    }
+
+    #[test]
+    fn messages_that_arent_utf8_are_refused() {
+        let sqs_fs = mount(&[], &["jobs"]);
+
+        let error = write_file(&sqs_fs, "jobs", b"\xff").unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"");
+    }
+
+    #[test]
+    fn lines_are_sent_once_they_fill_a_batch() {
+        let sqs_fs = mount(&["--write-mode", "lines"], &["jobs"]);
+        let fh = sqs_fs.create_file_handler(WRITE, &lookup(&sqs_fs, "jobs"), pid());
+
+        let lines: String = (0..MAX_BATCH_SIZE + 2).map(|line| format!("{}\n", line)).collect();
+        sqs_fs.write(fh, 0, format!("{}partial", lines).as_bytes(), false).unwrap();
+        assert_eq!(read_messages(&sqs_fs, "jobs").len(), MAX_BATCH_SIZE + 2);
+
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert_eq!(read_messages(&sqs_fs, "jobs"), [b"partial"]);
+    }
+
+    #[test]
+    fn lines_are_sent_without_the_ones_that_are_refused() {
+        let sqs_fs = mount(&["--write-mode", "lines", "--delimiter", "nul"], &["jobs"]);
+
+        let error = write_file(&sqs_fs, "jobs", b"first\0\xff\0\0third").unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        assert_eq!(read_messages(&sqs_fs, "jobs"), [b"first", b"third"]);
+    }
+
+    #[test]
+    fn lines_that_were_sent_already_cant_be_written_again() {
+        let sqs_fs = mount(&["--write-mode", "lines"], &["jobs"]);
+        let fh = sqs_fs.create_file_handler(WRITE, &lookup(&sqs_fs, "jobs"), pid());
+
+        let lines: String = (0..MAX_BATCH_SIZE).map(|line| format!("{}\n", line)).collect();
+        sqs_fs.write(fh, 0, lines.as_bytes(), false).unwrap();
+        let error = sqs_fs.write(fh, 0, b"again\n", false).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        sqs_fs.release_file_handler(fh).unwrap();
+    }
+
+    #[test]
+    fn lines_written_past_the_line_being_written_are_refused() {
+        let sqs_fs = mount(&["--write-mode", "lines"], &["jobs"]);
+        let fh = sqs_fs.create_file_handler(WRITE, &lookup(&sqs_fs, "jobs"), pid());
+
+        let error = sqs_fs.write(fh, 1 << 42, b"hello\n", false).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+
+        sqs_fs.write(fh, 0, b"hello\n", false).unwrap();
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello");
+    }
+
+    #[test]
+    fn messages_are_read_across_several_reads() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+        write_file(&sqs_fs, "jobs", b"0123456789").unwrap();
+        write_file(&sqs_fs, "jobs", b"abcdefghij").unwrap();
+
+        // a handle only receives once, however many reads it takes to get through the message
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(read_to_end(&sqs_fs, fh, &metadata, 3), b"0123456789");
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"abcdefghij");
+    }
+
+    #[test]
+    fn batches_are_read_at_once() {
+        let sqs_fs = mount(&["--batch-size", "10"], &["jobs"]);
+        for body in ["first", "second\n", "third"] {
+            write_file(&sqs_fs, "jobs", body.as_bytes()).unwrap();
+        }
+
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"first\nsecond\nthird");
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"");
+    }
+
+    fn message(message_id: &str, body: &str) -> Message {
+        Message::builder().message_id(message_id).body(body).build()
+    }
+
+    /// What a reader gets for `messages` received together
+    fn layout(messages: &[Message], delimiter: Delimiter, stream: bool) -> Vec<u8> {
+        let mut settings = CliArgs::parse_from(["mountpoint-sqs", "/mnt"]).queue_settings("jobs");
+        settings.delimiter = delimiter;
+        settings.stream = stream;
+
+        let mut buffer = vec![];
+        for msg in messages {
+            append_message(&mut buffer, msg, &settings);
+        }
+        buffer
+    }
+
+    #[test]
+    fn messages_are_separated_by_the_delimiter() {
+        let messages = [message("1", "first"), message("2", "second\n"), message("3", "third")];
+
+        assert_eq!(layout(&messages, Delimiter::Newline, false), b"first\nsecond\nthird");
+        assert_eq!(layout(&messages, Delimiter::Nul, false), b"first\0second\n\0third");
+        assert_eq!(layout(&messages, Delimiter::JsonLines, false), concat!(
+            r#"{"body":"first","message_id":"1"}"#, "\n",
+            r#"{"body":"second\n","message_id":"2"}"#, "\n",
+            r#"{"body":"third","message_id":"3"}"#, "\n",
+        ).as_bytes());
+    }
+
+    #[test]
+    fn streamed_messages_are_terminated_by_the_delimiter() {
+        let messages = [message("1", "first"), message("2", "second\n")];
+
+        assert_eq!(layout(&messages, Delimiter::Newline, true), b"first\nsecond\n");
+        assert_eq!(layout(&messages, Delimiter::Nul, true), b"first\0second\n\0");
+    }
+
+    #[test]
+    fn binary_data_is_encoded_only_when_it_isnt_utf8() {
+        let sqs_fs = mount(&["--binary"], &["jobs"]);
+        let queue_url = lookup(&sqs_fs, "jobs").queue_url;
+
+        write_file(&sqs_fs, "jobs", b"\xff\x00binary").unwrap();
+        let received = sqs_fs.backend.receive_message(&queue_url, 1, 0).unwrap();
+        assert!(is_base64_encoded(&received[0]));
+        assert_eq!(received[0].body(), Some("/wBiaW5hcnk="));
+        assert_eq!(message_body(&received[0], true), b"\xff\x00binary");
+        // without binary mode, encoded messages are served as they are
+        assert_eq!(message_body(&received[0], false), b"/wBiaW5hcnk=");
+
+        write_file(&sqs_fs, "jobs", "héllo".as_bytes()).unwrap();
+        let received = sqs_fs.backend.receive_message(&queue_url, 1, 0).unwrap();
+        assert!(!is_base64_encoded(&received[0]));
+        assert_eq!(received[0].body(), Some("héllo"));
+    }
+
+    #[test]
+    fn utf8_data_with_control_characters_is_encoded_too() {
+        let sqs_fs = mount(&["--binary"], &["jobs"]);
+        let queue_url = lookup(&sqs_fs, "jobs").queue_url;
+
+        write_file(&sqs_fs, "jobs", b"\x0a\x03foo").unwrap();
+        let received = sqs_fs.backend.receive_message(&queue_url, 1, 0).unwrap();
+        assert!(is_base64_encoded(&received[0]));
+        assert_eq!(message_body(&received[0], true), b"\x0a\x03foo");
+    }
+
+    #[test]
+    fn control_characters_need_binary_mode() {
+        let sqs_fs = mount(&[], &["jobs"]);
+
+        let error = write_file(&sqs_fs, "jobs", b"\x03").unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        assert_eq!(count(&sqs_fs, "jobs"), (0, 0));
+    }
+
+    #[test]
+    fn binary_data_reads_back_as_written() {
+        let sqs_fs = mount(&["--binary"], &["jobs"]);
+
+        write_file(&sqs_fs, "jobs", b"\xff\x00binary").unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"\xff\x00binary");
+    }
+
+    #[test]
+    fn encoded_messages_say_so_in_json_lines() {
+        let encoded = build_message(b"\xff".to_vec(), true).unwrap();
+        let msg = Message::builder()
+            .message_id("1")
+            .body(encoded.body)
+            .set_message_attributes(Some(encoded.attributes))
+            .build();
+
+        assert_eq!(layout(&[msg], Delimiter::JsonLines, false), concat!(r#"{"body":"/w==","encoding":"base64","message_id":"1"}"#, "\n").as_bytes());
+    }
+
+    #[test]
+    fn at_least_once_deletes_messages_read_to_the_end() {
+        let sqs_fs = mount_without_visibility_timeout(&["--read-mode", "at-least-once"], &["jobs"]);
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello");
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"");
+    }
+
+    #[test]
+    fn at_least_once_keeps_messages_not_read_to_the_end() {
+        let sqs_fs = mount_without_visibility_timeout(&["--read-mode", "at-least-once"], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(sqs_fs.read(fh, &metadata, 0, 2, pid()).unwrap(), b"he");
+        sqs_fs.release_file_handler(fh).unwrap();
+
+        // left to reappear once its visibility timeout lapses
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello");
+    }
+
+    #[test]
+    fn fsync_acknowledges_messages_not_read_to_the_end() {
+        let sqs_fs = mount_without_visibility_timeout(&["--read-mode", "at-least-once"], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(sqs_fs.read(fh, &metadata, 0, 2, pid()).unwrap(), b"he");
+        sqs_fs.acknowledge(fh).unwrap();
+        sqs_fs.release_file_handler(fh).unwrap();
+
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"");
+    }
+
+    #[test]
+    fn peek_leaves_messages_in_the_queue() {
+        let sqs_fs = mount(&["--read-mode", "peek"], &["jobs"]);
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello");
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello");
+    }
+
+    #[test]
+    fn reads_wait_for_messages_as_long_as_the_queue_says() {
+        let sqs_fs = mount(&["--read-mode", "at-least-once", "--queue-option", "jobs:wait_time_secs=20"], &["jobs", "events"]);
+
+        thread::scope(|scope| {
+            let reader = scope.spawn(|| read_file(&sqs_fs, "jobs"));
+            write_file(&sqs_fs, "jobs", b"hello").unwrap();
+            assert_eq!(reader.join().unwrap(), b"hello");
+        });
+
+        // other queues keep the mount-wide wait time
+        assert_eq!(read_file(&sqs_fs, "events"), b"");
+    }
+
+    #[test]
+    fn streams_serve_newline_terminated_messages_at_growing_offsets() {
+        let sqs_fs = mount(&["--stream"], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+        write_file(&sqs_fs, "jobs", b"first").unwrap();
+        write_file(&sqs_fs, "jobs", b"second\n").unwrap();
+
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(sqs_fs.read(fh, &metadata, 0, 3, pid()).unwrap(), b"fir");
+        assert_eq!(sqs_fs.read(fh, &metadata, 3, 4096, pid()).unwrap(), b"st\n");
+        // messages that already end with a newline don't get another one
+        assert_eq!(sqs_fs.read(fh, &metadata, 6, 4096, pid()).unwrap(), b"second\n");
+        sqs_fs.release_file_handler(fh).unwrap();
+    }
+
+    #[test]
+    fn at_least_once_streams_delete_messages_once_read_past() {
+        let sqs_fs = mount_without_visibility_timeout(&["--read-mode", "at-least-once", "--stream"], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+        write_file(&sqs_fs, "jobs", b"first").unwrap();
+        write_file(&sqs_fs, "jobs", b"second").unwrap();
+
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(sqs_fs.read(fh, &metadata, 0, 4096, pid()).unwrap(), b"first\n");
+        // asking for what comes next deletes the first message, but not the second one
+        assert_eq!(sqs_fs.read(fh, &metadata, 6, 4096, pid()).unwrap(), b"second\n");
+        sqs_fs.release_file_handler(fh).unwrap();
+
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(sqs_fs.read(fh, &metadata, 0, 4096, pid()).unwrap(), b"second\n");
+        sqs_fs.release_file_handler(fh).unwrap();
+    }
+
+    #[test]
+    fn fifo_queues_drop_duplicates() {
+        let sqs_fs = mount(&["--message-group-id", "group"], &["jobs.fifo"]);
+        write_file(&sqs_fs, "jobs.fifo", b"hello").unwrap();
+        write_file(&sqs_fs, "jobs.fifo", b"hello").unwrap();
+
+        assert_eq!(count(&sqs_fs, "jobs.fifo"), (1, 0));
+    }
+
+    #[test]
+    fn fifo_queues_take_ids_set_on_the_writer_handle() {
+        let sqs_fs = mount(&[], &["jobs.fifo"]);
+        let metadata = lookup(&sqs_fs, "jobs.fifo");
+
+        // there's no group ID to fall back on
+        let error = write_file(&sqs_fs, "jobs.fifo", b"hello").unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+
+        for _ in 0..2 {
+            let fh = sqs_fs.create_file_handler(WRITE, &metadata, pid());
+            sqs_fs.set_xattr(&metadata, pid(), GROUP_ID_XATTR, Some("group".to_string())).unwrap();
+            sqs_fs.set_xattr(&metadata, pid(), DEDUPLICATION_ID_XATTR, Some("id".to_string())).unwrap();
+            sqs_fs.write(fh, 0, b"hello", false).unwrap();
+            sqs_fs.release_file_handler(fh).unwrap();
+        }
+        assert_eq!(count(&sqs_fs, "jobs.fifo"), (1, 0));
+
+        // a deduplication ID can't outlive the handle it was set on
+        let error = sqs_fs.set_xattr(&metadata, pid(), DEDUPLICATION_ID_XATTR, Some("id".to_string())).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+    }
+
+    #[test]
+    fn fifo_groups_are_read_in_order() {
+        let sqs_fs = mount(&["--read-mode", "at-least-once", "--message-group-id", "group"], &["jobs.fifo"]);
+        write_file(&sqs_fs, "jobs.fifo", b"first").unwrap();
+        write_file(&sqs_fs, "jobs.fifo", b"second").unwrap();
+
+        let metadata = lookup(&sqs_fs, "jobs.fifo");
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(read_to_end(&sqs_fs, fh, &metadata, 4096), b"first");
+
+        // the group is held back until the first message is deleted
+        assert_eq!(read_file(&sqs_fs, "jobs.fifo"), b"");
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs.fifo"), b"second");
+    }
+
+    #[test]
+    fn attribute_values_may_start_with_their_data_type() {
+        let value = parse_attribute_value("hello").unwrap();
+        assert_eq!((value.data_type(), value.string_value()), ("String", Some("hello")));
+        let value = parse_attribute_value("text:with:colons").unwrap();
+        assert_eq!((value.data_type(), value.string_value()), ("String", Some("text:with:colons")));
+        let value = parse_attribute_value("Number.float:4.2").unwrap();
+        assert_eq!((value.data_type(), value.string_value()), ("Number.float", Some("4.2")));
+        let value = parse_attribute_value("Binary:/w==").unwrap();
+        assert_eq!((value.data_type(), value.binary_value().map(|blob| blob.as_ref())), ("Binary", Some(&b"\xff"[..])));
+
+        assert!(parse_attribute_value("Number:forty-two").is_err());
+        assert!(parse_attribute_value("Binary:not base64").is_err());
+    }
+
+    #[test]
+    fn attribute_values_are_formatted_the_way_they_are_parsed() {
+        for value in ["hello", "Number:42", "Number.float:4.2", "Binary:/w==", "String.label:text", "String:Number:42"] {
+            assert_eq!(format_attribute_value(&parse_attribute_value(value).unwrap()), value);
+        }
+        // strings looking like another type keep their data type
+        let value = MessageAttributeValue::builder().data_type("String").string_value("Number:42").build().unwrap();
+        assert_eq!(format_attribute_value(&value), "String:Number:42");
+    }
+
+    #[test]
+    fn attributes_are_attached_to_messages() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+
+        sqs_fs.set_xattr(&metadata, pid(), "user.sqs.attr.priority", Some("Number:1".to_string())).unwrap();
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+
+        let received = sqs_fs.backend.receive_message(&metadata.queue_url, 1, 0).unwrap();
+        let attributes = received[0].message_attributes().unwrap();
+        assert_eq!(attributes["priority"].string_value(), Some("1"));
+    }
+
+    #[test]
+    fn xattrs_describe_the_last_message_read() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+        assert!(sqs_fs.list_xattr(&metadata, pid()).is_empty());
+
+        sqs_fs.set_xattr(&metadata, pid(), "user.sqs.attr.priority", Some("Number:1".to_string())).unwrap();
+        write_file(&sqs_fs, "jobs", b"first").unwrap();
+        write_file(&sqs_fs, "jobs", b"second").unwrap();
+
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(read_to_end(&sqs_fs, fh, &metadata, 4096), b"first");
+        let message_id = sqs_fs.get_xattr(&metadata, pid(), MESSAGE_ID_XATTR).unwrap();
+        assert_eq!(sqs_fs.get_xattr(&metadata, pid(), RECEIVE_COUNT_XATTR), Some(b"1".to_vec()));
+        assert_eq!(sqs_fs.get_xattr(&metadata, pid(), "user.sqs.attr.priority"), Some(b"Number:1".to_vec()));
+        assert_eq!(sqs_fs.list_xattr(&metadata, pid()), ["user.sqs.attr.priority", MESSAGE_ID_XATTR, RECEIVE_COUNT_XATTR, SENT_TIMESTAMP_XATTR]);
+
+        // once the file is closed, the last message read from the queue by anyone is described
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert_eq!(sqs_fs.get_xattr(&metadata, pid(), MESSAGE_ID_XATTR), Some(message_id.clone()));
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"second");
+        assert_ne!(sqs_fs.get_xattr(&metadata, pid(), MESSAGE_ID_XATTR), Some(message_id));
+    }
+
+    #[test]
+    fn xattrs_set_on_the_queue_read_back_as_set() {
+        let sqs_fs = mount(&[], &["jobs", "events"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+
+        sqs_fs.set_xattr(&metadata, pid(), DELAY_SECONDS_XATTR, Some("60".to_string())).unwrap();
+        sqs_fs.set_xattr(&metadata, pid(), "user.sqs.attr.tenant", Some("acme".to_string())).unwrap();
+        assert_eq!(sqs_fs.get_xattr(&metadata, pid(), DELAY_SECONDS_XATTR), Some(b"60".to_vec()));
+        assert_eq!(sqs_fs.list_xattr(&metadata, pid()), ["user.sqs.attr.tenant", DELAY_SECONDS_XATTR]);
+        assert!(sqs_fs.list_xattr(&lookup(&sqs_fs, "events"), pid()).is_empty());
+
+        sqs_fs.set_xattr(&metadata, pid(), DELAY_SECONDS_XATTR, None).unwrap();
+        assert_eq!(sqs_fs.get_xattr(&metadata, pid(), DELAY_SECONDS_XATTR), None);
+    }
+
+    #[test]
+    fn xattrs_set_on_an_open_file_read_back_through_it() {
+        let sqs_fs = mount(&[], &["jobs.fifo"]);
+        let metadata = lookup(&sqs_fs, "jobs.fifo");
+
+        let fh = sqs_fs.create_file_handler(WRITE, &metadata, pid());
+        sqs_fs.set_xattr(&metadata, pid(), GROUP_ID_XATTR, Some("group".to_string())).unwrap();
+        sqs_fs.set_xattr(&metadata, pid(), DEDUPLICATION_ID_XATTR, Some("order-1".to_string())).unwrap();
+        assert_eq!(sqs_fs.get_xattr(&metadata, pid(), GROUP_ID_XATTR), Some(b"group".to_vec()));
+        assert_eq!(sqs_fs.list_xattr(&metadata, pid()), [DEDUPLICATION_ID_XATTR, GROUP_ID_XATTR]);
+        // other processes don't see them
+        assert!(sqs_fs.list_xattr(&metadata, pid() + 1).is_empty());
+
+        // they go away along with the file
+        sqs_fs.release_file_handler(fh).unwrap();
+        assert!(sqs_fs.get_xattr(&metadata, pid(), GROUP_ID_XATTR).is_none());
+    }
+
+    #[test]
+    fn xattrs_set_take_precedence_over_the_last_message_read() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+
+        sqs_fs.set_xattr(&metadata, pid(), "user.sqs.attr.priority", Some("Number:1".to_string())).unwrap();
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"hello");
+
+        sqs_fs.set_xattr(&metadata, pid(), "user.sqs.attr.priority", Some("Number:2".to_string())).unwrap();
+        assert_eq!(sqs_fs.get_xattr(&metadata, pid(), "user.sqs.attr.priority"), Some(b"Number:2".to_vec()));
+        assert!(sqs_fs.get_xattr(&metadata, pid(), MESSAGE_ID_XATTR).is_some());
+    }
+
+    #[test]
+    fn invalid_attributes_are_refused() {
+        let sqs_fs = mount(&[], &["jobs"]);
+        let metadata = lookup(&sqs_fs, "jobs");
+
+        for name in ["user.sqs.attr.", "user.sqs.attr.sqsfs-encoding"] {
+            let error = sqs_fs.set_xattr(&metadata, pid(), name, Some("hello".to_string())).unwrap_err();
+            assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        }
+
+        for i in 0..=MAX_MESSAGE_ATTRIBUTES {
+            sqs_fs.set_xattr(&metadata, pid(), &format!("user.sqs.attr.{}", i), Some("hello".to_string())).unwrap();
+        }
+        let error = write_file(&sqs_fs, "jobs", b"hello").unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+    }
+
+    #[test]
+    fn delays_come_from_the_path_the_xattr_or_the_mount() {
+        let sqs_fs = mount(&["--queue-option", "jobs:delay_seconds=60"], &["jobs", "events"]);
+
+        write_file(&sqs_fs, "events", b"hello").unwrap();
+        assert_eq!(count(&sqs_fs, "events"), (1, 0));
+        write_file(&sqs_fs, "jobs", b"hello").unwrap();
+        assert_eq!(count(&sqs_fs, "jobs"), (0, 1));
+
+        write_file(&sqs_fs, "events@delay=60", b"hello").unwrap();
+        assert_eq!(count(&sqs_fs, "events"), (1, 1));
+
+        sqs_fs.set_xattr(&lookup(&sqs_fs, "events"), pid(), DELAY_SECONDS_XATTR, Some("60".to_string())).unwrap();
+        write_file(&sqs_fs, "events", b"hello").unwrap();
+        assert_eq!(count(&sqs_fs, "events"), (1, 2));
+    }
+
+    #[test]
+    fn queues_looked_up_with_options_keep_their_inode() {
+        let sqs_fs = mount(&[], &["jobs"]);
+
+        let alias = lookup(&sqs_fs, "jobs@delay=60,group=group");
+        assert!(alias.file_attr.ino >= DYNAMIC_INODE_START);
+        assert_eq!(lookup(&sqs_fs, "jobs@delay=60,group=group").file_attr.ino, alias.file_attr.ino);
+        assert_eq!(sqs_fs.find_by_inode(alias.file_attr.ino).unwrap().unwrap().path_options.delay_seconds, Some(60));
+        assert!(sqs_fs.find_by_path("events@delay=60").unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_delays_are_refused() {
+        let sqs_fs = mount(&[], &["jobs"]);
+
+        let error = sqs_fs.set_xattr(&lookup(&sqs_fs, "jobs"), pid(), DELAY_SECONDS_XATTR, Some("901".to_string())).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        for name in ["jobs@delay=-1", "jobs@delay=soon", "jobs@unknown=1"] {
+            let error = sqs_fs.find_by_path(name).err().unwrap();
+            assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        }
+    }
+
+    #[test]
+    fn fifo_queues_ignore_the_delay_of_the_mount() {
+        let sqs_fs = mount(&["--delay-seconds", "60", "--message-group-id", "group"], &["jobs.fifo"]);
+        write_file(&sqs_fs, "jobs.fifo", b"hello").unwrap();
+
+        assert_eq!(count(&sqs_fs, "jobs.fifo"), (1, 0));
+    }
+
+    #[test]
+    fn fifo_queues_refuse_delays_of_their_own() {
+        let sqs_fs = mount(&[], &["jobs", "jobs.fifo"]);
+
+        let error = sqs_fs.set_xattr(&lookup(&sqs_fs, "jobs.fifo"), pid(), DELAY_SECONDS_XATTR, Some("10".to_string())).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        let error = sqs_fs.find_by_path("jobs.fifo@delay=10").err().unwrap();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+
+        assert!(sqs_fs.find_by_path("jobs@delay=10").unwrap().is_some());
+    }
+
+
+    #[test]
+    fn scheduled_messages_are_sent_once_due() {
+        let spool_dir = std::env::temp_dir().join(format!("sqsfs-scheduled-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&spool_dir);
+        let sqs_fs = mount(&["--spool-dir", spool_dir.to_str().unwrap()], &["jobs"]);
+
+        let parent = lookup(&sqs_fs, "scheduled/jobs");
+        for (name, body) in [("2000-01-01T00:00:00Z", "past"), ("2999-01-01T00:00:00Z", "future")] {
+            sqs_fs.create(&parent, name, FileType::RegularFile).unwrap().unwrap();
+            write_file(&sqs_fs, &format!("scheduled/jobs/{}", name), body.as_bytes()).unwrap();
+        }
+        assert_eq!(count(&sqs_fs, "jobs"), (0, 0));
+
+        let wait = sqs_fs.send_scheduled(sqs_fs.spool.as_ref().unwrap());
+        assert_eq!(wait, SCHEDULER_MAX_WAIT);
+        assert_eq!(read_file(&sqs_fs, "jobs"), b"past");
+        let files = sqs_fs.list_files(parent.file_attr.ino).unwrap().unwrap();
+        assert_eq!(files.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["2999-01-01T00:00:00Z"]);
+        assert!(!sqs_fs.dynamic_inodes.lock().unwrap().inodes.contains_key("scheduled/jobs/2000-01-01T00:00:00Z"));
+
+        std::fs::remove_dir_all(&spool_dir).unwrap();
+    }
+
+    #[test]
+    fn scheduled_messages_need_a_time_for_a_name() {
+        let spool_dir = std::env::temp_dir().join(format!("sqsfs-scheduled-names-{}", std::process::id()));
+        let sqs_fs = mount(&["--spool-dir", spool_dir.to_str().unwrap()], &["jobs"]);
+
+        let error = sqs_fs.create(&lookup(&sqs_fs, "scheduled/jobs"), "tomorrow", FileType::RegularFile).err().unwrap();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        // only scheduled/<queue> directories take messages
+        assert!(sqs_fs.create(&lookup(&sqs_fs, "scheduled"), "2999-01-01T00:00:00Z", FileType::RegularFile).unwrap().is_none());
+
+        std::fs::remove_dir_all(&spool_dir).unwrap();
+    }
+
+    #[test]
+    fn queues_are_directories_in_the_directories_layout() {
+        let sqs_fs = mount(&["--layout", "directories"], &["jobs"]);
+
+        let queue_dir = lookup(&sqs_fs, "jobs");
+        assert_eq!(queue_dir.file_attr.kind, FileType::Directory);
+        let files = sqs_fs.list_files(queue_dir.file_attr.ino).unwrap().unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["attributes", "inflight", "messages", "peek", "receive", "send", "stats"]);
+    }
+
+    #[test]
+    fn queue_files_allow_what_they_are_for() {
+        let (read, write) = (libc::R_OK as u16, libc::W_OK as u16);
+
+        assert!(Node::Messages.allows(read | write));
+        assert!(Node::Peek.allows(read) && !Node::Peek.allows(write));
+        assert!(Node::Send.allows(write) && !Node::Send.allows(read));
+        assert!(Node::Stats.allows(read) && !Node::Stats.allows(write));
+        assert!(!Node::QueueDir.allows(read));
+    }
+
+    #[test]
+    fn messages_are_sent_peeked_and_received_through_the_queue_directory() {
+        let sqs_fs = mount_without_visibility_timeout(&["--layout", "directories"], &["jobs"]);
+
+        write_file(&sqs_fs, "jobs/send", b"hello").unwrap();
+        assert_eq!(read_file(&sqs_fs, "jobs/peek"), b"hello");
+        assert_eq!(read_file(&sqs_fs, "jobs/messages"), b"hello");
+        assert_eq!(count(&sqs_fs, "jobs"), (0, 0));
+    }
+
+    #[test]
+    fn the_messages_file_follows_the_read_mode() {
+        let sqs_fs = mount(&["--layout", "directories", "--read-mode", "at-least-once"], &["jobs"]);
+        write_file(&sqs_fs, "jobs/send", b"hello").unwrap();
+
+        let metadata = lookup(&sqs_fs, "jobs/messages");
+        let fh = sqs_fs.create_file_handler(READ, &metadata, pid());
+        assert_eq!(sqs_fs.read(fh, &metadata, 0, 2, pid()).unwrap(), b"he");
+        sqs_fs.release_file_handler(fh).unwrap();
+
+        // not read to the end, so left to reappear once its visibility timeout lapses
+        assert_eq!(count(&sqs_fs, "jobs"), (0, 1));
+    }
+
+    #[test]
+    fn stats_and_attributes_are_read_from_the_queue_directory() {
+        let sqs_fs = mount(&["--layout", "directories"], &["jobs"]);
+
+        write_file(&sqs_fs, "jobs/send", b"hello").unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&read_file(&sqs_fs, "jobs/stats")).unwrap();
+        assert_eq!(stats, json!({"visible": 1, "in_flight": 0, "delayed": 0}));
+        assert_eq!(read_file(&sqs_fs, "jobs/attributes/VisibilityTimeout"), b"30\n");
+        assert!(sqs_fs.find_by_path("jobs/attributes/Unknown").unwrap().is_none());
+        // there's no link without a redrive policy
+        assert!(sqs_fs.find_by_path("jobs/dlq").unwrap().is_none());
+    }
+
+    #[test]
+    fn dead_letter_queues_are_named_after_the_redrive_policy() {
+        let redrive_policy = r#"{"deadLetterTargetArn": "arn:aws:sqs:us-east-1:123456789012:jobs-dlq", "maxReceiveCount": 5}"#;
+        assert_eq!(dead_letter_queue_name(redrive_policy).as_deref(), Some("jobs-dlq"));
+        assert_eq!(dead_letter_queue_name("{}"), None);
+        assert_eq!(dlq_link_target("jobs-dlq"), "../jobs-dlq");
+    }
+
+    #[test]
+    fn messages_are_received_into_inflight_by_the_receive_file() {
+        let sqs_fs = mount(&["--layout", "directories"], &["jobs"]);
+        write_file(&sqs_fs, "jobs/send", b"first").unwrap();
+        write_file(&sqs_fs, "jobs/send", b"second").unwrap();
+
+        // listing the directory doesn't receive anything
+        let inflight_ino = lookup(&sqs_fs, "jobs/inflight").file_attr.ino;
+        assert!(sqs_fs.list_files(inflight_ino).unwrap().unwrap().is_empty());
+        assert_eq!(count(&sqs_fs, "jobs"), (2, 0));
+
+        let message_id = String::from_utf8(read_file(&sqs_fs, "jobs/receive")).unwrap();
+        let message_id = message_id.trim_end();
+        let files = sqs_fs.list_files(inflight_ino).unwrap().unwrap();
+        assert_eq!(files.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), [message_id]);
+        assert_eq!(count(&sqs_fs, "jobs"), (1, 1));
+
+        let path = format!("jobs/inflight/{}", message_id);
+        assert_eq!(read_file(&sqs_fs, &path), b"first");
+
+        let metadata = lookup(&sqs_fs, &path);
+        sqs_fs.remove(&metadata).unwrap();
+        assert!(sqs_fs.list_files(inflight_ino).unwrap().unwrap().is_empty());
+        assert_eq!(count(&sqs_fs, "jobs"), (1, 0));
+
+        // the inode of the message is forgotten along with it
+        assert!(sqs_fs.find_by_inode(metadata.file_attr.ino).unwrap().is_none());
+        assert!(!sqs_fs.dynamic_inodes.lock().unwrap().inodes.contains_key(&path));
+    }
+
+    #[test]
+    fn only_scheduled_and_inflight_messages_can_be_removed() {
+        let sqs_fs = mount(&["--layout", "directories"], &["jobs"]);
+
+        let error = sqs_fs.remove(&lookup(&sqs_fs, "jobs/messages")).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::ENOTSUP);
+    }
+
+    #[test]
+    fn queues_are_created_and_removed_with_allow_queue_admin() {
+        let sqs_fs = mount(&["--allow-queue-admin"], &["jobs"]);
+
+        let root = sqs_fs.find_by_inode(ROOT_INODE).unwrap().unwrap();
+        let events = sqs_fs.create(&root, "events", FileType::RegularFile).unwrap().unwrap();
+        assert_eq!(events.node, Node::Queue);
+        write_file(&sqs_fs, "events", b"hello").unwrap();
+        assert_eq!(read_file(&sqs_fs, "events"), b"hello");
+        // queues are directories in the directories layout only
+        assert!(sqs_fs.create(&root, "other", FileType::Directory).unwrap().is_none());
+
+        sqs_fs.remove(&lookup(&sqs_fs, "events")).unwrap();
+        assert!(sqs_fs.find_by_path("events").unwrap().is_none());
+        // removing an alias leaves the queue alone
+        assert!(sqs_fs.remove(&lookup(&sqs_fs, "jobs@delay=60")).is_err());
+        assert!(sqs_fs.find_by_path("jobs").unwrap().is_some());
+    }
+
+    #[test]
+    fn queues_are_only_created_and_removed_with_allow_queue_admin() {
+        let sqs_fs = mount(&["--layout", "directories"], &["jobs"]);
+
+        let root = sqs_fs.find_by_inode(ROOT_INODE).unwrap().unwrap();
+        let error = sqs_fs.create(&root, "events", FileType::Directory).err().unwrap();
+        assert_eq!(sqs::to_errno(&error), libc::EPERM);
+        let error = sqs_fs.remove(&lookup(&sqs_fs, "jobs")).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EPERM);
+        assert!(sqs_fs.find_by_path("jobs/messages").unwrap().is_some());
+    }
+
+    #[test]
+    fn created_queues_are_kept_until_they_are_listed() {
+        let sqs_fs = mount(&["--allow-queue-admin", "--cache-ttl-in-secs", "0"], &["jobs"]);
+
+        let root = sqs_fs.find_by_inode(ROOT_INODE).unwrap().unwrap();
+        let events = sqs_fs.create(&root, "events", FileType::RegularFile).unwrap().unwrap();
+        // listing queues doesn't show it yet
+        sqs_fs.backend.delete_queue(&events.queue_url).unwrap();
+        assert!(sqs_fs.find_by_path("events").unwrap().is_some());
+
+        // once it's listed, listing queues has the last word again
+        sqs_fs.backend.create_queue("events").unwrap();
+        assert!(sqs_fs.find_by_path("events").unwrap().is_some());
+        sqs_fs.backend.delete_queue(&events.queue_url).unwrap();
+        assert!(sqs_fs.find_by_path("events").unwrap().is_none());
+    }
+
+    #[test]
+    fn deleted_queues_are_kept_away_until_they_are_no_longer_listed() {
+        let sqs_fs = mount(&["--allow-queue-admin", "--cache-ttl-in-secs", "0"], &["jobs"]);
+
+        let jobs = lookup(&sqs_fs, "jobs");
+        sqs_fs.remove(&jobs).unwrap();
+        // listing queues still shows it
+        sqs_fs.backend.create_queue("jobs").unwrap();
+        assert!(sqs_fs.find_by_path("jobs").unwrap().is_none());
+
+        // once it's no longer listed, listing queues has the last word again
+        sqs_fs.backend.delete_queue(&jobs.queue_url).unwrap();
+        assert!(sqs_fs.find_by_path("jobs").unwrap().is_none());
+        sqs_fs.backend.create_queue("jobs").unwrap();
+        assert!(sqs_fs.find_by_path("jobs").unwrap().is_some());
+    }
+
+    #[test]
+    fn queues_can_be_written_from_several_threads() {
+        let sqs_fs = mount(&[], &["jobs"]);
+
+        thread::scope(|scope| {
+            for i in 0..4 {
+                let sqs_fs = &sqs_fs;
+                scope.spawn(move || write_file(sqs_fs, "jobs", i.to_string().as_bytes()).unwrap());
+            }
+        });
+
+        let mut bodies: Vec<Vec<u8>> = (0..4).map(|_| read_file(&sqs_fs, "jobs")).collect();
+        bodies.sort();
+        assert_eq!(bodies, [b"0", b"1", b"2", b"3"]);
     }
 }
@@ -1,50 +1,154 @@
 use std::ffi::OsStr;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use fuser::{
-    FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen,
-    ReplyWrite, Request, TimeOrNow,
+    FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyOpen, ReplyWrite, ReplyXattr, Request, TimeOrNow,
 };
 use log::{debug, error, info, warn};
+use tokio::runtime::Runtime;
 
+use crate::backend::QueueBackend;
 use crate::cli::CliArgs;
-use crate::filesystem::{Metadata, SQSFileSystem};
+use crate::filesystem::{Metadata, Node, SQSFileSystem};
+use crate::sqs;
 
 pub struct SQSFuse {
-    sqs_fs: SQSFileSystem,
+    sqs_fs: Arc<SQSFileSystem>,
     default_ttl: Duration,
+    // fuser hands us one request at a time, so anything that may end up waiting on SQS runs on
+    // this pool and replies from there. That way a slow receive on one queue doesn't hold up
+    // requests for the others.
+    workers: Runtime,
 }
 
 impl SQSFuse {
-    pub fn new(cli_args: CliArgs) -> Self {
-        SQSFuse {
-            default_ttl: Duration::from_secs(cli_args.cache_ttl_in_secs),
-            sqs_fs: SQSFileSystem::new(cli_args),
-        }
+    pub fn new(cli_args: CliArgs, backend: Box<dyn QueueBackend>) -> anyhow::Result<Self> {
+        let workers = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("sqsfs-worker")
+            .build()?;
+
+        let default_ttl = Duration::from_secs(cli_args.cache_ttl_in_secs);
+        let sqs_fs = Arc::new(SQSFileSystem::new(cli_args, backend)?);
+        sqs_fs.start_scheduler()?;
+
+        Ok(SQSFuse {
+            default_ttl,
+            sqs_fs,
+            workers,
+        })
+    }
+
+    fn dispatch<F>(&self, op: F)
+    where
+        F: FnOnce(&SQSFileSystem) + Send + 'static,
+    {
+        let sqs_fs = self.sqs_fs.clone();
+        self.workers.spawn_blocking(move || op(&sqs_fs));
+    }
+
+    /// Run `op` on the workers with the metadata of `ino`, or reply with an error if it can't be
+    /// found
+    fn dispatch_with_metadata<R, F>(&self, ino: u64, reply: R, op: F)
+    where
+        R: ErrorReply + Send + 'static,
+        F: FnOnce(&SQSFileSystem, Metadata, R) + Send + 'static,
+    {
+        self.dispatch(move |sqs_fs| match sqs_fs.find_by_inode(ino) {
+            Ok(Some(metadata)) => op(sqs_fs, metadata, reply),
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(error) => {
+                error!("{:#}", error);
+                reply.error(sqs::to_errno(&error));
+            }
+        });
+    }
+
+    /// Create a file or directory without opening it, see `SQSFileSystem::create`
+    fn make_entry(&self, req: &Request<'_>, parent: u64, name: &OsStr, kind: FileType, reply: ReplyEntry) {
+        let name = name.to_string_lossy().to_string();
+        let (uid, gid) = (req.uid(), req.gid());
+        let ttl = self.default_ttl;
+        self.dispatch_with_metadata(parent, reply, move |sqs_fs, parent, reply| {
+            if !check_access(&parent, uid, gid, libc::W_OK as u16) {
+                reply.error(libc::EACCES);
+                return;
+            }
+
+            match sqs_fs.create(&parent, &name, kind) {
+                Ok(Some(metadata)) => reply.entry(&ttl, &metadata.file_attr, 0),
+                Ok(None) => reply.error(libc::ENOSYS),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
+            }
+        });
+    }
+
+    /// Remove a file or directory, see `SQSFileSystem::remove`
+    fn remove_entry(&self, parent: u64, name: &OsStr, kind: FileType, reply: ReplyEmpty) {
+        let name = name.to_string_lossy().to_string();
+        self.dispatch(move |sqs_fs| {
+            // Check if file exists
+            let metadata = match sqs_fs.find_by_name(parent, &name) {
+                Ok(Some(metadata)) => metadata,
+                Ok(None) => {
+                    reply.error(libc::ENOENT);
+                    return;
+                }
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                    return;
+                }
+            };
+
+            // unlink doesn't remove directories and rmdir removes nothing else
+            if kind != metadata.file_attr.kind && (kind == FileType::Directory || metadata.file_attr.kind == FileType::Directory) {
+                reply.error(match kind {
+                    FileType::Directory => libc::ENOTDIR,
+                    _ => libc::EISDIR,
+                });
+                return;
+            }
+
+            match sqs_fs.remove(&metadata) {
+                Ok(()) => reply.ok(),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
+            }
+        });
     }
 }
 
 impl Filesystem for SQSFuse {
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let fname = name.to_os_string().into_string().unwrap();
-        if parent == 1 && self.sqs_fs.has_file(&fname) {
-            let metadata = self.sqs_fs.find_by_name(&fname).unwrap();
-            reply.entry(&self.default_ttl, &metadata.file_attr, 0);
-        } else {
-            reply.error(libc::ENOENT);
-        }
+        let name = name.to_string_lossy().to_string();
+
+        let ttl = self.default_ttl;
+        self.dispatch(move |sqs_fs| {
+            match sqs_fs.find_by_name(parent, &name) {
+                Ok(Some(metadata)) => reply.entry(&ttl, &metadata.file_attr, 0),
+                Ok(None) => reply.error(libc::ENOENT),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
+            }
+        });
     }
 
     fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
         info!("getattr: ino:{ino}");
-        let file_metadata = self.sqs_fs.find_by_inode(ino);
-
-        if file_metadata.is_some() {
-            let metadata = file_metadata.unwrap();
-            reply.attr(&self.default_ttl, &metadata.file_attr);
-        } else {
-            reply.error(libc::ENOENT);
-        }
+        let ttl = self.default_ttl;
+        self.dispatch_with_metadata(ino, reply, move |_, metadata, reply| {
+            reply.attr(&ttl, &metadata.file_attr);
+        });
     }
 
     fn setattr(
@@ -71,38 +175,43 @@ impl Filesystem for SQSFuse {
             ino, mode, uid, gid, size, fh, flags
         );
 
-        let metadata = match self.sqs_fs.find_by_inode(ino) {
-            Some(metadata) => metadata,
-            None => {
-                reply.error(libc::ENOENT);
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            if mode.is_some() {
+                warn!(
+                    "chmod() isn't supported - \
+                    files given the same uid/gid of the user whom mounted sqsfs"
+                );
+                reply.error(libc::ENOSYS);
                 return;
             }
-        };
-
-        if mode.is_some() {
-            warn!(
-                "chmod() isn't supported - \
-                files given the same uid/gid of the user whom mounted sqsfs"
-            );
-            reply.error(libc::ENOSYS);
-            return;
-        }
 
-        if size.is_some() {
-            warn!(
-                "truncate() or O_TRUNC flag aren't supported as this doesn't make much sense in \
-            the SQS queues context. Ignoring operation...."
-            );
-        }
+            if let (Node::ScheduledMessage(_), Some(size)) = (&metadata.node, size) {
+                if let Err(error) = sqs_fs.truncate_scheduled(&metadata, size) {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                    return;
+                }
+            } else if size.is_some() {
+                warn!(
+                    "truncate() or O_TRUNC flag aren't supported as this doesn't make much sense in \
+                the SQS queues context. Ignoring operation...."
+                );
+            }
 
-        if atime.is_some() || mtime.is_some() {
-            warn!("utimens() isn't supported");
-            reply.error(libc::ENOSYS);
-            return;
-        }
+            // times can't be changed, but failing would make touch fail after creating a queue
+            if atime.is_some() || mtime.is_some() {
+                debug!("utimens() isn't supported. Ignoring operation....");
+            }
 
-        reply.attr(&Duration::new(0, 0), &metadata.file_attr.into());
-        return;
+            match sqs_fs.find_by_inode(ino) {
+                Ok(Some(metadata)) => reply.attr(&Duration::new(0, 0), &metadata.file_attr),
+                Ok(None) => reply.error(libc::ENOENT),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
+            }
+        });
     }
 
     /// Open a file.
@@ -113,7 +222,7 @@ impl Filesystem for SQSFuse {
     /// anything in fh. There are also some flags (direct_io, keep_cache) which the
     /// filesystem may set, to change the way the file is opened. See fuse_file_info
     /// structure in <fuse_common.h> for more details.
-    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+    fn open(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
         debug!("open(ino: {:#x?}, flags: {:#x?})", ino, flags,);
 
         // Check access mode
@@ -135,29 +244,24 @@ impl Filesystem for SQSFuse {
             }
         };
 
-        // Check if file exists
-        let metadata = match self.sqs_fs.find_by_inode(ino) {
-            Some(metadata) => metadata,
-            None => {
-                reply.error(libc::ENOENT);
+        let (uid, gid, pid) = (req.uid(), req.gid(), req.pid());
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            // Check if user has sufficient permissions, and if the file can be opened that way at
+            // all, e.g. peek is read-only even for root
+            if !check_access(&metadata, uid, gid, access_mask) || !metadata.node.allows(access_mask) {
+                reply.error(libc::EACCES);
                 return;
             }
-        };
-
-        // Check if user has sufficient permissions
-        if !check_access(metadata, _req, access_mask) {
-            reply.error(libc::EACCES);
-            return;
-        }
 
-        // create file handle
-        let fh = self.sqs_fs.create_file_handler(access_mask);
-        reply.opened(fh, 0);
+            // create file handle
+            let fh = sqs_fs.create_file_handler(access_mask, &metadata, pid);
+            reply.opened(fh, sqs_fs.open_flags(&metadata));
+        });
     }
 
     fn read(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         ino: u64,
         fh: u64,
         offset: i64,
@@ -178,25 +282,18 @@ impl Filesystem for SQSFuse {
             return;
         }
 
-        // Check if file exists
-        let metadata = match self.sqs_fs.find_by_inode(ino) {
-            Some(metadata) => metadata.clone(),
-            None => {
-                reply.error(libc::ENOENT);
-                return;
-            }
-        };
-
-        // Read from SQS
-        match self.sqs_fs.read(&metadata) {
-            Ok(content) => reply.data(content.as_bytes()),
-            Err(error) => {
-                // print error for troubleshooting purposes
-                error!("{}", error.to_string());
-                reply.error(libc::ENODATA);
-                return;
+        let pid = req.pid();
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            // Read from SQS
+            match sqs_fs.read(fh, &metadata, offset, size, pid) {
+                Ok(content) => reply.data(&content),
+                Err(error) => {
+                    // print error for troubleshooting purposes
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
             }
-        }
+        });
     }
 
     fn write(
@@ -229,34 +326,33 @@ impl Filesystem for SQSFuse {
             return;
         }
 
-        // SQS accepts UTF-8 messages, can we convert data into utf-8?
-        let msg = match String::from_utf8(data.to_vec()) {
-            Ok(msg) => msg,
-            Err(_) => {
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
-
-        // Check if file exists
-        let metadata = match self.sqs_fs.find_by_inode(ino) {
-            Some(metadata) => metadata.clone(),
-            None => {
-                reply.error(libc::ENOENT);
-                return;
+        let data = data.to_vec();
+        let append = flags & libc::O_APPEND != 0;
+        self.dispatch(move |sqs_fs| {
+            // Buffer data until the file gets closed
+            match sqs_fs.write(fh, offset, &data, append) {
+                Ok(written) => reply.written(written),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
             }
-        };
+        });
+    }
 
-        // Send data to SQS
-        let written = match self.sqs_fs.write(&metadata, msg.as_str()) {
-            Ok(written) => written,
-            Err(_) => {
-                reply.error(libc::EINVAL);
-                return;
+    /// Called on each close() of the file, its errors are returned by close()
+    fn flush(&mut self, _req: &Request<'_>, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        debug!("flush ino: {ino} fh: {fh} lock_owner: {lock_owner}");
+        self.dispatch(move |sqs_fs| {
+            // Send data to SQS
+            match sqs_fs.flush(fh) {
+                Ok(()) => reply.ok(),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
             }
-        };
-
-        reply.written(written);
+        });
     }
 
     fn release(
@@ -270,8 +366,117 @@ impl Filesystem for SQSFuse {
         reply: ReplyEmpty,
     ) {
         debug!("release ino: {ino} fh: {fh} flags: {flags}");
-        self.sqs_fs.release_file_handler(fh);
-        reply.ok();
+        self.dispatch(move |sqs_fs| {
+            // the kernel ignores errors from release, so they can only be logged
+            if let Err(error) = sqs_fs.release_file_handler(fh) {
+                error!("{:#}", error);
+            }
+            reply.ok();
+        });
+    }
+
+    fn fsync(&mut self, _req: &Request<'_>, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        debug!("fsync ino: {ino} fh: {fh} datasync: {datasync}");
+        self.dispatch(move |sqs_fs| {
+            match sqs_fs.flush(fh).and_then(|()| sqs_fs.acknowledge(fh)) {
+                Ok(()) => reply.ok(),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
+            }
+        });
+    }
+
+    fn setxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+        reply: ReplyEmpty,
+    ) {
+        debug!(
+            "setxattr(ino: {:#x?}, name: {:?}, value.len(): {}, flags: {:#x?}, position: {})",
+            ino, name, value.len(), flags, position
+        );
+
+        let name = name.to_string_lossy().to_string();
+        let value = match String::from_utf8(value.to_vec()) {
+            Ok(value) => value,
+            Err(_) => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+
+        let pid = req.pid();
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            match sqs_fs.set_xattr(&metadata, pid, &name, Some(value)) {
+                Ok(()) => reply.ok(),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
+            }
+        });
+    }
+
+    fn removexattr(&mut self, req: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        debug!("removexattr(ino: {:#x?}, name: {:?})", ino, name);
+
+        let name = name.to_string_lossy().to_string();
+        let pid = req.pid();
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            match sqs_fs.set_xattr(&metadata, pid, &name, None) {
+                Ok(()) => reply.ok(),
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                }
+            }
+        });
+    }
+
+    fn getxattr(&mut self, req: &Request<'_>, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        debug!("getxattr(ino: {:#x?}, name: {:?}, size: {})", ino, name, size);
+
+        let name = name.to_string_lossy().to_string();
+        let pid = req.pid();
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            match sqs_fs.get_xattr(&metadata, pid, &name) {
+                Some(value) => reply_xattr(reply, size, &value),
+                None => reply.error(libc::ENODATA),
+            }
+        });
+    }
+
+    fn listxattr(&mut self, req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        debug!("listxattr(ino: {:#x?}, size: {})", ino, size);
+
+        let pid = req.pid();
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            // names are handed over as a list of NUL-terminated strings
+            let mut names = vec![];
+            for name in sqs_fs.list_xattr(&metadata, pid) {
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+            }
+            reply_xattr(reply, size, &names);
+        });
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        debug!("readlink(ino: {:#x?})", ino);
+
+        self.dispatch_with_metadata(ino, reply, move |sqs_fs, metadata, reply| {
+            match sqs_fs.read_link(&metadata) {
+                Some(target) => reply.data(target.as_bytes()),
+                None => reply.error(libc::EINVAL),
+            }
+        });
     }
 
     fn readdir(
@@ -284,42 +489,163 @@ impl Filesystem for SQSFuse {
     ) {
         debug!("readdir ino: {ino} fh: {_fh} offset: {offset}");
 
-        if ino != 1 {
-            reply.error(libc::ENOENT);
-            return;
-        }
+        self.dispatch(move |sqs_fs| {
+            let mut entries = vec![
+                (ino, FileType::Directory, ".".to_string()),
+                (1, FileType::Directory, "..".to_string()),
+            ];
 
-        let mut entries = vec![
-            (1, FileType::Directory, ".".to_string()),
-            (1, FileType::Directory, "..".to_string()),
-        ];
-
-        for file in self.sqs_fs.list_files() {
-            entries.push((
-                file.file_attr.ino,
-                file.file_attr.kind,
-                file.queue_name.clone(),
-            ));
-        }
+            let files = match sqs_fs.list_files(ino) {
+                Ok(Some(files)) => files,
+                Ok(None) => {
+                    reply.error(libc::ENOENT);
+                    return;
+                }
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                    return;
+                }
+            };
+
+            for (name, file) in files {
+                entries.push((
+                    file.file_attr.ino,
+                    file.file_attr.kind,
+                    name,
+                ));
+            }
+
+            for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+                // i + 1 means the index of the next entry
+                if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                    break;
+                }
+            }
+
+            reply.ok();
+        });
+    }
 
-        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
-            // i + 1 means the index of the next entry
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
-                break;
+    /// Only scheduled messages and, with --allow-queue-admin, queues can be created
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        debug!(
+            "create(parent: {:#x?}, name: {:?}, mode: {:#o}, umask: {:#o}, flags: {:#x?})",
+            parent, name, mode, umask, flags
+        );
+
+        let access_mask = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => libc::R_OK as u16,
+            libc::O_WRONLY => libc::W_OK as u16,
+            _ => libc::R_OK as u16 | libc::W_OK as u16,
+        };
+
+        let name = name.to_string_lossy().to_string();
+        let (uid, gid, pid) = (req.uid(), req.gid(), req.pid());
+        let ttl = self.default_ttl;
+        self.dispatch_with_metadata(parent, reply, move |sqs_fs, parent, reply| {
+            if !check_access(&parent, uid, gid, libc::W_OK as u16) {
+                reply.error(libc::EACCES);
+                return;
             }
+
+            let metadata = match sqs_fs.create(&parent, &name, FileType::RegularFile) {
+                Ok(Some(metadata)) => metadata,
+                Ok(None) => {
+                    reply.error(libc::ENOSYS);
+                    return;
+                }
+                Err(error) => {
+                    error!("{:#}", error);
+                    reply.error(sqs::to_errno(&error));
+                    return;
+                }
+            };
+
+            let fh = sqs_fs.create_file_handler(access_mask, &metadata, pid);
+            reply.created(&ttl, &metadata.file_attr, 0, fh, sqs_fs.open_flags(&metadata));
+        });
+    }
+
+    fn mknod(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        debug!(
+            "mknod(parent: {:#x?}, name: {:?}, mode: {:#o}, umask: {:#o}, rdev: {})",
+            parent, name, mode, umask, rdev
+        );
+
+        // queues and scheduled messages are regular files, there's nothing else to create
+        if mode & libc::S_IFMT != libc::S_IFREG {
+            reply.error(libc::EPERM);
+            return;
         }
+        self.make_entry(req, parent, name, FileType::RegularFile, reply);
+    }
+
+    /// Only queues can be created as directories, in the directories layout
+    fn mkdir(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, umask: u32, reply: ReplyEntry) {
+        debug!("mkdir(parent: {:#x?}, name: {:?}, mode: {:#o}, umask: {:#o})", parent, name, mode, umask);
+
+        self.make_entry(req, parent, name, FileType::Directory, reply);
+    }
+
+    /// Only scheduled messages, which cancels them, received messages, which deletes them, and
+    /// queues, with --allow-queue-admin, can be removed
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        debug!("unlink(parent: {:#x?}, name: {:?})", parent, name);
+
+        self.remove_entry(parent, name, FileType::RegularFile, reply);
+    }
+
+    /// Only queues can be removed as directories, in the directories layout
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        debug!("rmdir(parent: {:#x?}, name: {:?})", parent, name);
 
-        reply.ok();
+        self.remove_entry(parent, name, FileType::Directory, reply);
     }
 }
 
-fn check_access(file_metadata: &Metadata, req: &Request, access_mask: u16) -> bool {
+/// The replies `SQSFuse::dispatch_with_metadata` can fail with, fuser gives them no common trait
+trait ErrorReply {
+    fn error(self, err: libc::c_int);
+}
+
+macro_rules! impl_error_reply {
+    ($($reply:ty),*) => {
+        $(impl ErrorReply for $reply {
+            fn error(self, err: libc::c_int) {
+                <$reply>::error(self, err)
+            }
+        })*
+    };
+}
+
+impl_error_reply!(ReplyAttr, ReplyCreate, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyXattr);
+
+fn check_access(file_metadata: &Metadata, uid: u32, gid: u32, access_mask: u16) -> bool {
     let mut owner = false;
     let mut group = false;
     let mut others = false;
 
     // root is allowed to read & write anything
-    if req.uid() == 0 {
+    if uid == 0 {
         return true;
     }
     // Scratchpad
@@ -328,13 +654,62 @@ fn check_access(file_metadata: &Metadata, req: &Request, access_mask: u16) -> bo
     // group  r  = 4 = 100
     // others r  = 4 = 100
 
-    if file_metadata.file_attr.uid == req.uid() {
+    if file_metadata.file_attr.uid == uid {
         owner = access_mask & (file_metadata.file_attr.perm >> 6) > 0;
-    } else if file_metadata.file_attr.gid == req.gid() {
+    } else if file_metadata.file_attr.gid == gid {
         group = access_mask & (file_metadata.file_attr.perm >> 3) > 0;
     } else {
         others = access_mask & (file_metadata.file_attr.perm) > 0;
     }
 
-    return owner | group | others;
+    owner | group | others
+}
+
+/// Reply to getxattr and listxattr, which ask for the size of the value first when `size` is zero
+fn reply_xattr(reply: ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::memory::InMemoryBackend;
+
+    const READ: u16 = libc::R_OK as u16;
+    const WRITE: u16 = libc::W_OK as u16;
+
+    /// Metadata of a queue owned by uid 1000 and gid 100, with the given permissions
+    fn queue_metadata(perm: u16) -> Metadata {
+        let cli_args = CliArgs::parse_from(["mountpoint-sqs", "/mnt"]);
+        let sqs_fs = SQSFileSystem::new(cli_args, Box::new(InMemoryBackend::new(&["jobs".to_string()]))).unwrap();
+
+        let mut metadata = sqs_fs.find_by_name(fuser::FUSE_ROOT_ID, "jobs").unwrap().unwrap();
+        metadata.file_attr.uid = 1000;
+        metadata.file_attr.gid = 100;
+        metadata.file_attr.perm = perm;
+        metadata
+    }
+
+    #[test]
+    fn owner_group_and_others_get_their_own_permissions() {
+        let metadata = queue_metadata(0o640);
+
+        assert!(check_access(&metadata, 1000, 100, READ | WRITE));
+        assert!(check_access(&metadata, 1001, 100, READ));
+        assert!(!check_access(&metadata, 1001, 100, WRITE));
+        assert!(!check_access(&metadata, 1001, 101, READ));
+    }
+
+    #[test]
+    fn root_can_access_anything() {
+        assert!(check_access(&queue_metadata(0o000), 0, 0, READ | WRITE));
+    }
 }
@@ -1,5 +1,8 @@
+mod backend;
 mod cli;
 mod fuse;
+mod memory;
+mod spool;
 mod sqs;
 mod filesystem;
 
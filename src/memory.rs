@@ -0,0 +1,554 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use aws_sdk_sqs::types::{Message, MessageAttributeValue, MessageSystemAttributeName, QueueAttributeName};
+
+use crate::backend::{BatchEntryFailure, MAX_MESSAGE_SIZE, OutgoingMessage, QueueBackend, is_valid_body};
+use crate::sqs;
+use crate::sqs::SQSError;
+
+/// Prefix given to queue URLs served by the in-memory backend
+pub const MEMORY_QUEUE_URL_PREFIX: &str = "memory://sqsfs/000000000000/";
+
+/// Same default visibility timeout used by SQS when creating a queue
+const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SQS won't accept queue names longer than that
+const MAX_QUEUE_NAME_LENGTH: usize = 80;
+
+/// For how long FIFO queues remember deduplication IDs
+const DEDUPLICATION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+struct StoredMessage {
+    message_id: String,
+    body: String,
+    attributes: HashMap<String, MessageAttributeValue>,
+    group_id: Option<String>,
+    sent_timestamp: SystemTime,
+    receive_count: u32,
+    receipt_handle: Option<String>,
+    visible_at: Instant,
+}
+
+#[derive(Default)]
+struct MemoryQueue {
+    messages: VecDeque<StoredMessage>,
+    fifo: bool,
+    /// Deduplication IDs seen recently by a FIFO queue, along with when they can be used again
+    deduplication_ids: HashMap<String, Instant>,
+}
+
+/// Queue backend that keeps messages in the mount process. It mimics the parts of SQS that
+/// sqsfs relies upon: messages become invisible for a while once received, each receive hands
+/// out a new receipt handle and only the latest receipt handle can delete the message. Queues
+/// named like FIFO queues deduplicate messages and hand out the messages of a group in order.
+pub struct InMemoryBackend {
+    queues: Mutex<BTreeMap<String, MemoryQueue>>,
+    // notified whenever a message is sent or made visible, for long polling
+    changed: Condvar,
+    visibility_timeout: Duration,
+    next_id: AtomicU64,
+}
+
+impl InMemoryBackend {
+    pub fn new(queue_names: &[String]) -> Self {
+        let queues = queue_names.iter()
+            .map(|name| {
+                let queue = MemoryQueue {
+                    fifo: sqs::is_fifo_queue(name),
+                    ..Default::default()
+                };
+                (format!("{MEMORY_QUEUE_URL_PREFIX}{name}"), queue)
+            })
+            .collect();
+
+        InMemoryBackend {
+            queues: Mutex::new(queues),
+            changed: Condvar::new(),
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            next_id: AtomicU64::default(),
+        }
+    }
+
+    /// Same backend, with received messages becoming visible again after `visibility_timeout`
+    #[cfg(test)]
+    pub fn with_visibility_timeout(self, visibility_timeout: Duration) -> Self {
+        InMemoryBackend { visibility_timeout, ..self }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+impl QueueBackend for InMemoryBackend {
+    fn list_queues(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self.queues.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn create_queue(&self, queue_name: &str) -> anyhow::Result<String> {
+        // same rules as SQS: up to 80 alphanumeric characters, hyphens and underscores, the .fifo
+        // suffix of FIFO queues included
+        let base_name = queue_name.strip_suffix(".fifo").unwrap_or(queue_name);
+        let valid = !base_name.is_empty()
+            && queue_name.len() <= MAX_QUEUE_NAME_LENGTH
+            && base_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(anyhow!("invalid queue name: {}", queue_name).context(SQSError::InvalidInput));
+        }
+
+        // creating a queue that already exists hands out its URL, like SQS does
+        let queue_url = format!("{MEMORY_QUEUE_URL_PREFIX}{queue_name}");
+        self.queues.lock().unwrap().entry(queue_url.clone()).or_insert_with(|| MemoryQueue {
+            fifo: sqs::is_fifo_queue(queue_name),
+            ..Default::default()
+        });
+        Ok(queue_url)
+    }
+
+    fn delete_queue(&self, queue_url: &str) -> anyhow::Result<()> {
+        self.queues.lock().unwrap().remove(queue_url)
+            .ok_or_else(|| queue_does_not_exist(queue_url))?;
+
+        // long polls on the queue find out it's gone
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    fn get_queue_attributes(&self, queue_url: &str) -> anyhow::Result<HashMap<QueueAttributeName, String>> {
+        let queues = self.queues.lock().unwrap();
+        let queue = queues.get(queue_url)
+            .ok_or_else(|| queue_does_not_exist(queue_url))?;
+
+        let now = Instant::now();
+        let visible = queue.messages.iter()
+            .filter(|msg| msg.visible_at <= now)
+            .count();
+
+        let mut attributes = HashMap::from([
+            (QueueAttributeName::ApproximateNumberOfMessages, visible.to_string()),
+            (QueueAttributeName::ApproximateNumberOfMessagesNotVisible, (queue.messages.len() - visible).to_string()),
+            (QueueAttributeName::VisibilityTimeout, self.visibility_timeout.as_secs().to_string()),
+            (QueueAttributeName::MaximumMessageSize, MAX_MESSAGE_SIZE.to_string()),
+        ]);
+        if queue.fifo {
+            attributes.insert(QueueAttributeName::FifoQueue, "true".to_string());
+            attributes.insert(QueueAttributeName::ContentBasedDeduplication, "false".to_string());
+        }
+
+        Ok(attributes)
+    }
+
+    fn send_message(&self, queue_url: &str, message: &OutgoingMessage) -> anyhow::Result<u32> {
+        if message.size() > MAX_MESSAGE_SIZE {
+            return Err(anyhow!("message length can't be above 256kb as per SQS limits")
+                .context(SQSError::MessageTooLarge));
+        }
+        if !is_valid_body(&message.body) {
+            return Err(anyhow!("message body contains characters SQS doesn't allow")
+                .context(SQSError::InvalidInput));
+        }
+
+        let message_id = format!("{:032x}", self.next_id());
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(queue_url)
+            .ok_or_else(|| queue_does_not_exist(queue_url))?;
+
+        if queue.fifo && message.delay_seconds.is_some() {
+            return Err(anyhow!("messages sent to FIFO queues can't have their own delay")
+                .context(SQSError::InvalidInput));
+        }
+
+        if queue.fifo {
+            let (Some(_), Some(deduplication_id)) = (&message.group_id, &message.deduplication_id) else {
+                return Err(anyhow!("messages sent to FIFO queues need a group ID and a deduplication ID")
+                    .context(SQSError::InvalidInput));
+            };
+
+            // like SQS, accept duplicates but drop them
+            let now = Instant::now();
+            queue.deduplication_ids.retain(|_, expires_at| *expires_at > now);
+            if queue.deduplication_ids.contains_key(deduplication_id) {
+                return Ok(message.body.len() as u32);
+            }
+            queue.deduplication_ids.insert(deduplication_id.clone(), now + DEDUPLICATION_INTERVAL);
+        }
+
+        let delay = Duration::from_secs(message.delay_seconds.unwrap_or_default().max(0) as u64);
+
+        queue.messages.push_back(StoredMessage {
+            message_id,
+            body: message.body.clone(),
+            attributes: message.attributes.clone(),
+            group_id: message.group_id.clone(),
+            sent_timestamp: SystemTime::now(),
+            receive_count: 0,
+            receipt_handle: None,
+            visible_at: Instant::now() + delay,
+        });
+        self.changed.notify_all();
+
+        Ok(message.body.len() as u32)
+    }
+
+    fn send_message_batch(&self, queue_url: &str, messages: &[OutgoingMessage]) -> anyhow::Result<Vec<BatchEntryFailure>> {
+        if !self.queues.lock().unwrap().contains_key(queue_url) {
+            return Err(queue_does_not_exist(queue_url));
+        }
+
+        // the only way sending a message to an existing queue fails is the message itself
+        let failures = messages.iter()
+            .enumerate()
+            .filter_map(|(index, message)| {
+                self.send_message(queue_url, message).err().map(|error| BatchEntryFailure {
+                    index,
+                    sender_fault: true,
+                    error,
+                })
+            })
+            .collect();
+
+        Ok(failures)
+    }
+
+    fn receive_message(&self, queue_url: &str, max_messages: i32, wait_time_secs: i32) -> anyhow::Result<Vec<Message>> {
+        let receipt_id = self.next_id();
+        let deadline = Instant::now() + Duration::from_secs(wait_time_secs.max(0) as u64);
+        let mut queues = self.queues.lock().unwrap();
+
+        loop {
+            let queue = queues.get_mut(queue_url)
+                .ok_or_else(|| queue_does_not_exist(queue_url))?;
+
+            let now = Instant::now();
+            let fifo = queue.fifo;
+            let mut busy_groups = HashSet::new();
+
+            let received: Vec<Message> = queue.messages.iter_mut()
+                .filter(|msg| {
+                    // messages of a FIFO group are held back while earlier ones are in flight
+                    let group_id = msg.group_id.as_deref().filter(|_| fifo);
+                    if msg.visible_at > now {
+                        busy_groups.extend(group_id.map(str::to_string));
+                        return false;
+                    }
+                    !group_id.is_some_and(|group_id| busy_groups.contains(group_id))
+                })
+                .take(max_messages.max(1) as usize)
+                .map(|stored| {
+                    let receipt_handle = format!("{}-{}", stored.message_id, receipt_id);
+                    stored.receive_count += 1;
+                    stored.receipt_handle = Some(receipt_handle.clone());
+                    stored.visible_at = now + self.visibility_timeout;
+
+                    let sent_timestamp = stored.sent_timestamp.duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis();
+
+                    Message::builder()
+                        .message_id(stored.message_id.clone())
+                        .receipt_handle(receipt_handle)
+                        .body(stored.body.clone())
+                        .set_message_attributes(Some(stored.attributes.clone()).filter(|attributes| !attributes.is_empty()))
+                        .attributes(MessageSystemAttributeName::ApproximateReceiveCount, stored.receive_count.to_string())
+                        .attributes(MessageSystemAttributeName::SentTimestamp, sent_timestamp.to_string())
+                        .build()
+                })
+                .collect();
+
+            if !received.is_empty() || now >= deadline {
+                return Ok(received);
+            }
+
+            // sleep until something is sent, an in-flight message becomes visible again or we
+            // run out of time, whichever comes first. Messages visible already are held back by
+            // their FIFO group, waiting on them would spin
+            let wake_up = queue.messages.iter()
+                .map(|msg| msg.visible_at)
+                .filter(|visible_at| *visible_at > now)
+                .min()
+                .map_or(deadline, |visible_at| visible_at.min(deadline));
+            queues = self.changed.wait_timeout(queues, wake_up - now).unwrap().0;
+        }
+    }
+
+    fn delete_message(&self, queue_url: &str, receipt_handle: &str) -> anyhow::Result<()> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(queue_url)
+            .ok_or_else(|| queue_does_not_exist(queue_url))?;
+
+        let position = queue.messages.iter()
+            .position(|msg| msg.receipt_handle.as_deref() == Some(receipt_handle))
+            .ok_or_else(|| anyhow!("receipt handle is invalid: {}", receipt_handle)
+                .context(SQSError::InvalidInput))?;
+
+        queue.messages.remove(position);
+        Ok(())
+    }
+
+    fn delete_message_batch(&self, queue_url: &str, receipt_handles: &[String]) -> anyhow::Result<()> {
+        // keep deleting past a failure, like SQS does, and report the first one
+        let mut result = Ok(());
+        for receipt_handle in receipt_handles {
+            if let Err(error) = self.delete_message(queue_url, receipt_handle) {
+                result = result.and(Err(error));
+            }
+        }
+        result
+    }
+
+    fn change_message_visibility(&self, queue_url: &str, receipt_handle: &str, visibility_timeout: i32) -> anyhow::Result<()> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(queue_url)
+            .ok_or_else(|| queue_does_not_exist(queue_url))?;
+
+        let stored = queue.messages.iter_mut()
+            .find(|msg| msg.receipt_handle.as_deref() == Some(receipt_handle))
+            .ok_or_else(|| anyhow!("receipt handle is invalid: {}", receipt_handle)
+                .context(SQSError::InvalidInput))?;
+
+        stored.visible_at = Instant::now() + Duration::from_secs(visibility_timeout.max(0) as u64);
+        self.changed.notify_all();
+        Ok(())
+    }
+}
+
+fn queue_does_not_exist(queue_url: &str) -> anyhow::Error {
+    anyhow!("{}", queue_url).context(SQSError::QueueDoesNotExist)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn backend(queue_names: &[&str]) -> InMemoryBackend {
+        let queue_names: Vec<String> = queue_names.iter().map(|name| name.to_string()).collect();
+        InMemoryBackend::new(&queue_names)
+    }
+
+    fn url(queue_name: &str) -> String {
+        format!("{MEMORY_QUEUE_URL_PREFIX}{queue_name}")
+    }
+
+    fn message(body: &str) -> OutgoingMessage {
+        OutgoingMessage::new(body.to_string())
+    }
+
+    fn fifo_message(body: &str, group_id: &str, deduplication_id: &str) -> OutgoingMessage {
+        OutgoingMessage {
+            group_id: Some(group_id.to_string()),
+            deduplication_id: Some(deduplication_id.to_string()),
+            ..message(body)
+        }
+    }
+
+    fn bodies(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|msg| msg.body().unwrap_or_default()).collect()
+    }
+
+    #[test]
+    fn received_messages_stay_invisible_until_deleted() {
+        let backend = backend(&["jobs"]);
+        backend.send_message(&url("jobs"), &message("hello")).unwrap();
+
+        let received = backend.receive_message(&url("jobs"), 1, 0).unwrap();
+        assert_eq!(bodies(&received), ["hello"]);
+        assert!(backend.receive_message(&url("jobs"), 1, 0).unwrap().is_empty());
+
+        backend.delete_message(&url("jobs"), received[0].receipt_handle().unwrap()).unwrap();
+        assert!(backend.queues.lock().unwrap()[&url("jobs")].messages.is_empty());
+    }
+
+    #[test]
+    fn only_the_latest_receipt_handle_deletes() {
+        let backend = InMemoryBackend {
+            visibility_timeout: Duration::ZERO,
+            ..backend(&["jobs"])
+        };
+        backend.send_message(&url("jobs"), &message("hello")).unwrap();
+
+        let first = backend.receive_message(&url("jobs"), 1, 0).unwrap();
+        let second = backend.receive_message(&url("jobs"), 1, 0).unwrap();
+        assert_eq!(second[0].attributes().unwrap()[&MessageSystemAttributeName::ApproximateReceiveCount], "2");
+
+        let error = backend.delete_message(&url("jobs"), first[0].receipt_handle().unwrap()).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        backend.delete_message(&url("jobs"), second[0].receipt_handle().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn batches_are_sent_past_refused_messages() {
+        let backend = backend(&["jobs"]);
+        let messages = [message("first"), message(&"a".repeat(MAX_MESSAGE_SIZE + 1)), message("third")];
+
+        let failures = backend.send_message_batch(&url("jobs"), &messages).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].index, 1);
+        assert!(failures[0].sender_fault);
+        assert_eq!(bodies(&backend.receive_message(&url("jobs"), 10, 0).unwrap()), ["first", "third"]);
+    }
+
+    #[test]
+    fn batches_are_deleted_past_invalid_receipt_handles() {
+        let backend = backend(&["jobs"]);
+        for body in ["first", "second", "third"] {
+            backend.send_message(&url("jobs"), &message(body)).unwrap();
+        }
+
+        let received = backend.receive_message(&url("jobs"), 10, 0).unwrap();
+        assert_eq!(bodies(&received), ["first", "second", "third"]);
+
+        let mut receipt_handles: Vec<String> = received.iter()
+            .map(|msg| msg.receipt_handle().unwrap().to_string())
+            .collect();
+        receipt_handles.insert(1, "invalid".to_string());
+        let error = backend.delete_message_batch(&url("jobs"), &receipt_handles).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        assert!(backend.queues.lock().unwrap()[&url("jobs")].messages.is_empty());
+    }
+
+    #[test]
+    fn messages_can_be_made_visible_again() {
+        let backend = backend(&["jobs"]);
+        backend.send_message(&url("jobs"), &message("hello")).unwrap();
+
+        let received = backend.receive_message(&url("jobs"), 1, 0).unwrap();
+        backend.change_message_visibility(&url("jobs"), received[0].receipt_handle().unwrap(), 0).unwrap();
+        assert_eq!(bodies(&backend.receive_message(&url("jobs"), 1, 0).unwrap()), ["hello"]);
+    }
+
+    #[test]
+    fn long_polls_wait_for_messages_to_arrive() {
+        let backend = backend(&["jobs"]);
+
+        thread::scope(|scope| {
+            let receiver = scope.spawn(|| backend.receive_message(&url("jobs"), 1, 20).unwrap());
+            backend.send_message(&url("jobs"), &message("hello")).unwrap();
+            assert_eq!(bodies(&receiver.join().unwrap()), ["hello"]);
+        });
+    }
+
+    #[test]
+    fn fifo_queues_drop_duplicates() {
+        let backend = backend(&["jobs.fifo"]);
+        backend.send_message(&url("jobs.fifo"), &fifo_message("first", "group", "id")).unwrap();
+        backend.send_message(&url("jobs.fifo"), &fifo_message("second", "group", "id")).unwrap();
+
+        assert_eq!(bodies(&backend.receive_message(&url("jobs.fifo"), 10, 0).unwrap()), ["first"]);
+    }
+
+    #[test]
+    fn fifo_groups_are_received_in_order() {
+        let backend = backend(&["jobs.fifo"]);
+        for (body, group_id) in [("a1", "a"), ("b1", "b"), ("a2", "a")] {
+            backend.send_message(&url("jobs.fifo"), &fifo_message(body, group_id, body)).unwrap();
+        }
+
+        // a2 is held back while a1 is in flight
+        let received = backend.receive_message(&url("jobs.fifo"), 1, 0).unwrap();
+        assert_eq!(bodies(&received), ["a1"]);
+        assert_eq!(bodies(&backend.receive_message(&url("jobs.fifo"), 10, 0).unwrap()), ["b1"]);
+
+        backend.delete_message(&url("jobs.fifo"), received[0].receipt_handle().unwrap()).unwrap();
+        assert_eq!(bodies(&backend.receive_message(&url("jobs.fifo"), 10, 0).unwrap()), ["a2"]);
+    }
+
+    #[test]
+    fn long_poll_gives_up_while_a_fifo_group_is_held_back() {
+        let backend = backend(&["jobs.fifo"]);
+        backend.send_message(&url("jobs.fifo"), &fifo_message("a1", "a", "a1")).unwrap();
+        backend.send_message(&url("jobs.fifo"), &fifo_message("a2", "a", "a2")).unwrap();
+        backend.receive_message(&url("jobs.fifo"), 1, 0).unwrap();
+
+        let started = Instant::now();
+        assert!(backend.receive_message(&url("jobs.fifo"), 10, 1).unwrap().is_empty());
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn fifo_queues_need_group_and_deduplication_ids() {
+        let backend = backend(&["jobs.fifo"]);
+
+        let error = backend.send_message(&url("jobs.fifo"), &message("hello")).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        let attributes = backend.get_queue_attributes(&url("jobs.fifo")).unwrap();
+        assert_eq!(attributes[&QueueAttributeName::FifoQueue], "true");
+        assert_eq!(attributes[&QueueAttributeName::ApproximateNumberOfMessages], "0");
+    }
+
+    #[test]
+    fn fifo_queues_refuse_delayed_messages() {
+        let backend = backend(&["jobs.fifo"]);
+        let message = OutgoingMessage {
+            delay_seconds: Some(10),
+            ..fifo_message("hello", "group", "id")
+        };
+
+        let error = backend.send_message(&url("jobs.fifo"), &message).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+    }
+
+    #[test]
+    fn delayed_messages_stay_invisible_until_due() {
+        let backend = backend(&["jobs"]);
+        let message = OutgoingMessage {
+            delay_seconds: Some(10),
+            ..message("hello")
+        };
+
+        backend.send_message(&url("jobs"), &message).unwrap();
+        assert!(backend.receive_message(&url("jobs"), 10, 0).unwrap().is_empty());
+        let attributes = backend.get_queue_attributes(&url("jobs")).unwrap();
+        assert_eq!(attributes[&QueueAttributeName::ApproximateNumberOfMessagesNotVisible], "1");
+    }
+
+    #[test]
+    fn messages_above_the_size_limit_are_refused() {
+        let backend = backend(&["jobs"]);
+
+        let error = backend.send_message(&url("jobs"), &message(&"a".repeat(MAX_MESSAGE_SIZE + 1))).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EFBIG);
+        assert!(backend.receive_message(&url("jobs"), 1, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_queues_are_refused() {
+        let backend = backend(&["jobs"]);
+
+        let error = backend.send_message(&url("events"), &message("hello")).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::ENOENT);
+        let error = backend.receive_message(&url("events"), 1, 0).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::ENOENT);
+    }
+
+    #[test]
+    fn control_characters_are_refused() {
+        let backend = backend(&["jobs"]);
+
+        let error = backend.send_message(&url("jobs"), &message("\u{3}")).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+    }
+
+    #[test]
+    fn queues_can_be_created_and_deleted() {
+        let backend = backend(&[]);
+
+        assert_eq!(backend.create_queue("jobs.fifo").unwrap(), url("jobs.fifo"));
+        let attributes = backend.get_queue_attributes(&url("jobs.fifo")).unwrap();
+        assert_eq!(attributes[&QueueAttributeName::FifoQueue], "true");
+
+        for name in ["", ".fifo", "jobs/1", &"a".repeat(MAX_QUEUE_NAME_LENGTH + 1)] {
+            let error = backend.create_queue(name).unwrap_err();
+            assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        }
+
+        backend.delete_queue(&url("jobs.fifo")).unwrap();
+        let error = backend.delete_queue(&url("jobs.fifo")).unwrap_err();
+        assert_eq!(sqs::to_errno(&error), libc::ENOENT);
+    }
+}
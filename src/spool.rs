@@ -0,0 +1,240 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context};
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+
+use crate::sqs::SQSError;
+
+/// Message waiting in the spool until it's due
+pub struct ScheduledMessage {
+    /// Time the message is due at, as given when scheduling it
+    pub name: String,
+    pub due: SystemTime,
+    pub size: u64,
+}
+
+/// Messages scheduled for later, kept on disk so that they survive restarts. Each queue has a
+/// directory of its own, holding a file per message named after the time it's due at.
+pub struct Spool {
+    dir: PathBuf,
+    /// Whether messages were scheduled since the scheduler last looked
+    changed: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl Spool {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)
+            .context(format!("Error while creating spool directory: {}", dir.display()))?;
+
+        Ok(Spool {
+            dir,
+            changed: Mutex::new(false),
+            wakeup: Condvar::new(),
+        })
+    }
+
+    /// Names of the queues that have had messages scheduled
+    pub fn queues(&self) -> anyhow::Result<Vec<String>> {
+        let entries = fs::read_dir(&self.dir)
+            .context(format!("Error while listing spool directory: {}", self.dir.display()))?;
+
+        let mut queues = vec![];
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                queues.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        Ok(queues)
+    }
+
+    /// Messages scheduled for a queue, soonest first. Files that aren't named after a time, e.g.
+    /// temporary files left behind by an editor, are skipped
+    pub fn list(&self, queue_name: &str) -> anyhow::Result<Vec<ScheduledMessage>> {
+        let dir = self.dir.join(queue_name);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error)
+                .context(format!("Error while listing spool directory: {}", dir.display())),
+        };
+
+        let mut messages = vec![];
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if let Some(message) = self.find(queue_name, &name)? {
+                messages.push(message);
+            }
+        }
+
+        messages.sort_by_key(|message| message.due);
+        Ok(messages)
+    }
+
+    pub fn find(&self, queue_name: &str, name: &str) -> anyhow::Result<Option<ScheduledMessage>> {
+        let Ok(due) = parse_due_time(name) else {
+            return Ok(None);
+        };
+
+        let path = self.dir.join(queue_name).join(name);
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => Ok(Some(ScheduledMessage {
+                name: name.to_string(),
+                due,
+                size: metadata.len(),
+            })),
+            Ok(_) => Ok(None),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error).context(format!("Error while looking up: {}", path.display())),
+        }
+    }
+
+    /// Schedule an empty message, filled in by the writes that follow
+    pub fn create(&self, queue_name: &str, name: &str) -> anyhow::Result<ScheduledMessage> {
+        let due = parse_due_time(name)?;
+
+        let dir = self.dir.join(queue_name);
+        fs::create_dir_all(&dir)
+            .context(format!("Error while creating spool directory: {}", dir.display()))?;
+
+        let path = dir.join(name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)
+            .context(format!("Error while creating: {}", path.display()))?;
+
+        Ok(ScheduledMessage {
+            name: name.to_string(),
+            due,
+            size: file.metadata()?.len(),
+        })
+    }
+
+    pub fn read(&self, queue_name: &str, name: &str, offset: u64, size: u32) -> anyhow::Result<Vec<u8>> {
+        let path = self.dir.join(queue_name).join(name);
+        let mut file = fs::File::open(&path)
+            .context(format!("Error while opening: {}", path.display()))?;
+
+        let mut data = vec![];
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(size as u64).read_to_end(&mut data)
+            .context(format!("Error while reading: {}", path.display()))?;
+        Ok(data)
+    }
+
+    pub fn write(&self, queue_name: &str, name: &str, offset: u64, data: &[u8], append: bool) -> anyhow::Result<()> {
+        let path = self.dir.join(queue_name).join(name);
+        let mut file = OpenOptions::new().write(true).append(append).open(&path)
+            .context(format!("Error while opening: {}", path.display()))?;
+
+        if !append {
+            file.seek(SeekFrom::Start(offset))?;
+        }
+        file.write_all(data)
+            .context(format!("Error while writing: {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn truncate(&self, queue_name: &str, name: &str, size: u64) -> anyhow::Result<()> {
+        let path = self.dir.join(queue_name).join(name);
+        OpenOptions::new().write(true).open(&path)
+            .and_then(|file| file.set_len(size))
+            .context(format!("Error while truncating: {}", path.display()))
+    }
+
+    pub fn remove(&self, queue_name: &str, name: &str) -> anyhow::Result<()> {
+        let path = self.dir.join(queue_name).join(name);
+        fs::remove_file(&path)
+            .context(format!("Error while removing: {}", path.display()))
+    }
+
+    /// Let the scheduler know about a message it may not have seen yet
+    pub fn notify(&self) {
+        *self.changed.lock().unwrap() = true;
+        self.wakeup.notify_all();
+    }
+
+    /// Wait for up to `timeout`, or until a message gets scheduled
+    pub fn wait(&self, timeout: Duration) {
+        let changed = self.changed.lock().unwrap();
+        let (mut changed, _) = self.wakeup.wait_timeout_while(changed, timeout, |changed| !*changed).unwrap();
+        *changed = false;
+    }
+}
+
+/// Parse the name of a scheduled message, an RFC 3339 timestamp such as 2025-06-01T08:00:00Z
+pub fn parse_due_time(name: &str) -> anyhow::Result<SystemTime> {
+    DateTime::from_str(name, Format::DateTimeWithOffset)
+        .map_err(|error| anyhow!(error))
+        .and_then(|due| Ok(SystemTime::try_from(due)?))
+        .context(SQSError::InvalidInput)
+        .context(format!("Scheduled messages must be named after an RFC 3339 timestamp, got: {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::sqs;
+
+    /// Spool in a directory of its own, emptied first
+    fn spool(name: &str) -> Spool {
+        let dir = std::env::temp_dir().join(format!("sqsfs-spool-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Spool::new(dir).unwrap()
+    }
+
+    #[test]
+    fn due_times_are_rfc_3339_timestamps() {
+        let due = parse_due_time("2025-06-01T08:00:00Z").unwrap();
+        assert_eq!(due, SystemTime::UNIX_EPOCH + Duration::from_secs(1748764800));
+        assert_eq!(parse_due_time("2025-06-01T10:00:00+02:00").unwrap(), due);
+
+        for name in ["2025-06-01", "tomorrow", ".2025-06-01T08:00:00Z.swp"] {
+            let error = parse_due_time(name).unwrap_err();
+            assert_eq!(sqs::to_errno(&error), libc::EINVAL);
+        }
+    }
+
+    #[test]
+    fn messages_are_listed_soonest_first() {
+        let spool = spool("list");
+        for name in ["2025-06-01T09:00:00Z", "2025-06-01T08:00:00Z", "2025-06-01T08:30:00+01:00"] {
+            spool.create("jobs", name).unwrap();
+        }
+        // left behind by an editor
+        fs::write(spool.dir.join("jobs").join(".swp"), "").unwrap();
+
+        let names: Vec<String> = spool.list("jobs").unwrap().into_iter().map(|message| message.name).collect();
+        assert_eq!(names, ["2025-06-01T08:30:00+01:00", "2025-06-01T08:00:00Z", "2025-06-01T09:00:00Z"]);
+        assert_eq!(spool.queues().unwrap(), ["jobs"]);
+        assert!(spool.list("events").unwrap().is_empty());
+
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+
+    #[test]
+    fn messages_are_written_and_read_back() {
+        let spool = spool("write");
+        let name = "2025-06-01T08:00:00Z";
+        spool.create("jobs", name).unwrap();
+
+        spool.write("jobs", name, 0, b"hello", false).unwrap();
+        spool.write("jobs", name, 0, b" world", true).unwrap();
+        assert_eq!(spool.read("jobs", name, 6, 4096).unwrap(), b"world");
+        assert_eq!(spool.find("jobs", name).unwrap().unwrap().size, 11);
+
+        spool.truncate("jobs", name, 5).unwrap();
+        assert_eq!(spool.read("jobs", name, 0, 4096).unwrap(), b"hello");
+        spool.remove("jobs", name).unwrap();
+        assert!(spool.find("jobs", name).unwrap().is_none());
+
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+}
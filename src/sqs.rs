@@ -1,101 +1,661 @@
-use anyhow::anyhow;
-use aws_config::BehaviorVersion;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use aws_config::{BehaviorVersion, Region};
+use aws_config::sts::AssumeRoleProvider;
+use aws_credential_types::provider::error::CredentialsError;
 use aws_sdk_sqs::Client;
-use aws_sdk_sqs::operation::delete_message::DeleteMessageOutput;
-use aws_sdk_sqs::operation::receive_message::ReceiveMessageOutput;
+use aws_sdk_sqs::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_sdk_sqs::config::interceptors::BeforeTransmitInterceptorContextRef;
+use aws_sdk_sqs::config::retry::RetryConfig;
+use aws_sdk_sqs::config::timeout::TimeoutConfig;
+use aws_sdk_sqs::error::{BoxError, ProvideErrorMetadata, SdkError};
+use aws_sdk_sqs::types::{
+    BatchResultErrorEntry, DeleteMessageBatchRequestEntry, Message, MessageAttributeValue,
+    MessageSystemAttributeName, QueueAttributeName, SendMessageBatchRequestEntry,
+};
+use log::warn;
+use tokio::runtime::Runtime;
 use url::Url;
 
+use crate::backend::{BatchEntryFailure, MAX_MESSAGE_SIZE, OutgoingMessage, QueueBackend};
+use crate::cli::{BackoffMode, CliArgs};
+
+/// Error codes returned by AWS when credentials are missing, expired or lack permissions
+const ACCESS_DENIED_CODES: &[&str] = &[
+    "AccessDenied",
+    "AccessDeniedException",
+    "ExpiredToken",
+    "ExpiredTokenException",
+    "InvalidClientTokenId",
+    "InvalidSecurityToken",
+    "KmsAccessDenied",
+    "MissingAuthenticationToken",
+    "SignatureDoesNotMatch",
+    "UnrecognizedClientException",
+];
+
+const QUEUE_DOES_NOT_EXIST_CODES: &[&str] = &[
+    "AWS.SimpleQueueService.NonExistentQueue",
+    "QueueDoesNotExist",
+];
+
+const THROTTLED_CODES: &[&str] = &[
+    "AWS.SimpleQueueService.RequestThrottled",
+    "KmsThrottled",
+    "OverLimit",
+    "RequestThrottled",
+    "Throttling",
+    "ThrottlingException",
+];
+
+const MESSAGE_TOO_LARGE_CODES: &[&str] = &[
+    "AWS.SimpleQueueService.BatchRequestTooLong",
+    "BatchRequestTooLong",
+];
+
+const INVALID_INPUT_CODES: &[&str] = &[
+    "AWS.SimpleQueueService.BatchEntryIdsNotDistinct",
+    "AWS.SimpleQueueService.EmptyBatchRequest",
+    "AWS.SimpleQueueService.InvalidBatchEntryId",
+    "AWS.SimpleQueueService.TooManyEntriesInBatchRequest",
+    "InvalidAttributeName",
+    "InvalidAttributeValue",
+    "InvalidIdFormat",
+    "InvalidMessageContents",
+    "InvalidParameterValue",
+    "MessageNotInflight",
+    "ReceiptHandleIsInvalid",
+];
+
+/// Failures that the FUSE layer should report with a specific errno. They are attached as
+/// context to the underlying error, so use [`to_errno`] to find them.
+#[derive(Debug)]
+pub enum SQSError {
+    AccessDenied,
+    QueueDoesNotExist,
+    Throttled,
+    Timeout,
+    Network,
+    MessageTooLarge,
+    InvalidInput,
+    /// The process waiting on the operation got a signal
+    Interrupted,
+    /// The operation doesn't apply to sqsfs
+    Unsupported,
+    /// The operation is disabled by the mount options
+    NotPermitted,
+}
+
+impl SQSError {
+    pub fn errno(&self) -> i32 {
+        match self {
+            SQSError::AccessDenied => libc::EACCES,
+            SQSError::QueueDoesNotExist => libc::ENOENT,
+            SQSError::Throttled => libc::EAGAIN,
+            SQSError::Timeout => libc::ETIMEDOUT,
+            SQSError::Network => libc::EIO,
+            SQSError::MessageTooLarge => libc::EFBIG,
+            SQSError::InvalidInput => libc::EINVAL,
+            SQSError::Interrupted => libc::EINTR,
+            SQSError::Unsupported => libc::ENOTSUP,
+            SQSError::NotPermitted => libc::EPERM,
+        }
+    }
+}
+
+impl Display for SQSError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SQSError::AccessDenied => write!(f, "access denied"),
+            SQSError::QueueDoesNotExist => write!(f, "queue does not exist"),
+            SQSError::Throttled => write!(f, "request throttled"),
+            SQSError::Timeout => write!(f, "request timed out"),
+            SQSError::Network => write!(f, "network failure"),
+            SQSError::MessageTooLarge => write!(f, "message too large"),
+            SQSError::InvalidInput => write!(f, "invalid input"),
+            SQSError::Interrupted => write!(f, "interrupted"),
+            SQSError::Unsupported => write!(f, "unsupported"),
+            SQSError::NotPermitted => write!(f, "not permitted"),
+        }
+    }
+}
+
+/// Map an error returned by a backend to an errno. Errors that haven't been classified are
+/// reported as EIO.
+pub fn to_errno(error: &anyhow::Error) -> i32 {
+    match error.downcast_ref::<SQSError>() {
+        Some(sqs_error) => sqs_error.errno(),
+        None => libc::EIO,
+    }
+}
+
+fn classify<E, R>(error: SdkError<E, R>) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + Error + Send + Sync + 'static,
+    R: Debug + Send + Sync + 'static,
+{
+    let kind = match &error {
+        SdkError::ServiceError(_) => classify_code(error.code(), error.message()),
+        SdkError::TimeoutError(_) => Some(SQSError::Timeout),
+        // credentials that can't be loaded (or a role that can't be assumed) never reach SQS
+        SdkError::DispatchFailure(_) if is_credentials_error(&error) => Some(SQSError::AccessDenied),
+        SdkError::DispatchFailure(failure) if failure.is_timeout() => Some(SQSError::Timeout),
+        SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => Some(SQSError::Network),
+        _ => None,
+    };
+
+    let error = anyhow::Error::new(error);
+    match kind {
+        Some(kind) => error.context(kind),
+        None => error,
+    }
+}
+
+fn classify_code(code: Option<&str>, message: Option<&str>) -> Option<SQSError> {
+    let code = code?;
+
+    if ACCESS_DENIED_CODES.contains(&code) {
+        Some(SQSError::AccessDenied)
+    } else if QUEUE_DOES_NOT_EXIST_CODES.contains(&code) {
+        Some(SQSError::QueueDoesNotExist)
+    } else if THROTTLED_CODES.contains(&code) {
+        Some(SQSError::Throttled)
+    } else if MESSAGE_TOO_LARGE_CODES.contains(&code)
+        // SQS doesn't have a dedicated error code for oversize messages
+        || message.is_some_and(|message| message.contains("must be shorter than")) {
+        Some(SQSError::MessageTooLarge)
+    } else if INVALID_INPUT_CODES.contains(&code) {
+        Some(SQSError::InvalidInput)
+    } else {
+        None
+    }
+}
+
+/// SQS doesn't like being sent an empty set of attributes
+fn message_attributes(message: &OutgoingMessage) -> Option<HashMap<String, MessageAttributeValue>> {
+    match message.attributes.is_empty() {
+        true => None,
+        false => Some(message.attributes.clone()),
+    }
+}
+
+/// Errors of batch entries come with the same codes as the errors of whole requests
+fn classify_batch_entry(failure: &BatchResultErrorEntry) -> anyhow::Error {
+    let error = anyhow!("{}: {}", failure.code(), failure.message().unwrap_or_default());
+    match classify_code(Some(failure.code()), failure.message()) {
+        Some(kind) => error.context(kind),
+        None => error,
+    }
+}
+
+fn is_credentials_error(error: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(cause) = source {
+        if cause.is::<CredentialsError>() {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// The SDK retries failed attempts on its own, this interceptor makes those retries visible
+#[derive(Debug)]
+struct RetryLogger {
+    operation: &'static str,
+    queue_url: Option<String>,
+    attempts: AtomicU32,
+}
+
+impl RetryLogger {
+    fn new(operation: &'static str, queue_url: Option<&str>) -> Self {
+        RetryLogger {
+            operation,
+            queue_url: queue_url.map(str::to_string),
+            attempts: AtomicU32::default(),
+        }
+    }
+}
+
+impl Intercept for RetryLogger {
+    fn name(&self) -> &'static str {
+        "RetryLogger"
+    }
+
+    fn read_before_attempt(
+        &self,
+        _context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+        if attempt > 1 {
+            match &self.queue_url {
+                Some(queue_url) => warn!("retrying {} on {} (attempt {})", self.operation, queue_url, attempt),
+                None => warn!("retrying {} (attempt {})", self.operation, attempt),
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct SQSClient {
     client: Client,
+    // FUSE callbacks are synchronous, so SDK calls are driven by this runtime. It lives as long as
+    // the client does, which keeps HTTP connections pooled across operations.
+    runtime: Runtime,
 }
 
 impl SQSClient {
-    #[tokio::main]
-    pub async fn new() -> Self {
-        let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-        SQSClient {
-            client: aws_sdk_sqs::Client::new(&config)
+    pub fn new(cli_args: &CliArgs) -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("sqsfs-sdk")
+            .enable_all()
+            .build()
+            .context("Error while creating tokio runtime")?;
+
+        let client = runtime.block_on(Self::build_client(cli_args))?;
+
+        Ok(SQSClient {
+            client,
+            runtime,
+        })
+    }
+
+    async fn build_client(cli_args: &CliArgs) -> anyhow::Result<Client> {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = &cli_args.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+        if let Some(profile) = &cli_args.profile {
+            loader = loader.profile_name(profile);
+        }
+
+        let config = loader.load().await;
+
+        // without a region the SDK would only fail once the first request is made, which makes
+        // it look like there are no queues rather than a misconfiguration
+        if config.region().is_none() {
+            return Err(match &cli_args.profile {
+                Some(profile) => anyhow!(
+                    "unable to resolve AWS region: profile '{}' has no region, use --region to set one",
+                    profile
+                ),
+                None => anyhow!(
+                    "unable to resolve AWS region: use --region, AWS_REGION or a profile with a region"
+                ),
+            });
         }
+
+        // the endpoint is only set for SQS so STS calls still go to the default STS endpoint
+        let mut sqs_config = aws_sdk_sqs::config::Builder::from(&config);
+        if let Some(endpoint_url) = &cli_args.endpoint_url {
+            sqs_config = sqs_config.endpoint_url(endpoint_url);
+        }
+
+        let retry_config = match cli_args.backoff_mode {
+            BackoffMode::Standard => RetryConfig::standard(),
+            BackoffMode::Adaptive => RetryConfig::adaptive(),
+        };
+        sqs_config = sqs_config.retry_config(retry_config.with_max_attempts(cli_args.max_attempts));
+
+        let mut timeout_config = match config.timeout_config() {
+            Some(timeout_config) => timeout_config.to_builder(),
+            None => TimeoutConfig::builder(),
+        };
+        if let Some(connect_timeout) = cli_args.connect_timeout_in_secs {
+            timeout_config = timeout_config.connect_timeout(Duration::from_secs(connect_timeout));
+        }
+        if let Some(operation_timeout) = cli_args.operation_timeout_in_secs {
+            timeout_config = timeout_config.operation_timeout(Duration::from_secs(operation_timeout));
+        }
+        sqs_config = sqs_config.timeout_config(timeout_config.build());
+
+        // credentials for the assumed role are cached by the SDK and refreshed ahead of their
+        // expiration, so long-lived mounts keep working
+        if let Some(role_arn) = &cli_args.role_arn {
+            let mut provider = AssumeRoleProvider::builder(role_arn)
+                .configure(&config)
+                .session_name(&cli_args.role_session_name);
+            if let Some(external_id) = &cli_args.external_id {
+                provider = provider.external_id(external_id);
+            }
+            sqs_config = sqs_config.credentials_provider(provider.build().await);
+        }
+
+        Ok(aws_sdk_sqs::Client::from_conf(sqs_config.build()))
     }
 
-    #[tokio::main]
     async fn list_queues_async(&self) -> anyhow::Result<Vec<String>> {
-        let response = self.client.list_queues()
-            .into_paginator()
-            .items()
+        let mut queues = vec![];
+        let mut next_token = None;
+
+        // pages are fetched by hand as paginators can't be customised with an interceptor
+        loop {
+            let response = self.client.list_queues()
+                .max_results(1000)
+                .set_next_token(next_token)
+                .customize()
+                .interceptor(RetryLogger::new("ListQueues", None))
+                .send()
+                .await
+                .map_err(classify)?;
+
+            queues.extend(response.queue_urls.unwrap_or_default());
+            next_token = response.next_token;
+
+            if next_token.is_none() {
+                return Ok(queues);
+            }
+        }
+    }
+
+    async fn create_queue_async(&self, queue_name: &str) -> anyhow::Result<String> {
+        let mut request = self.client.create_queue()
+            .queue_name(queue_name);
+        if is_fifo_queue(queue_name) {
+            request = request.attributes(QueueAttributeName::FifoQueue, "true");
+        }
+
+        let create_queue_output = request
+            .customize()
+            .interceptor(RetryLogger::new("CreateQueue", None))
+            .send()
+            .await
+            .map_err(classify)?;
+
+        create_queue_output.queue_url
+            .ok_or_else(|| anyhow!("No queue url returned for: {}", queue_name))
+    }
+
+    async fn delete_queue_async(&self, queue_url: &str) -> anyhow::Result<()> {
+        self.client.delete_queue()
+            .queue_url(queue_url)
+            .customize()
+            .interceptor(RetryLogger::new("DeleteQueue", Some(queue_url)))
             .send()
-            .try_collect()
-            .await?;
+            .await
+            .map_err(classify)?;
 
-        Ok(response)
+        Ok(())
     }
 
-    pub fn list_queues(&self) -> anyhow::Result<Vec<String>> {
-        self.list_queues_async()
+    async fn get_queue_attributes_async(&self, queue_url: &str) -> anyhow::Result<HashMap<QueueAttributeName, String>> {
+        let get_queue_attributes_output = self.client.get_queue_attributes()
+            .queue_url(queue_url)
+            .attribute_names(QueueAttributeName::All)
+            .customize()
+            .interceptor(RetryLogger::new("GetQueueAttributes", Some(queue_url)))
+            .send()
+            .await
+            .map_err(classify)?;
+
+        Ok(get_queue_attributes_output.attributes.unwrap_or_default())
     }
 
-    #[tokio::main]
-    async fn send_message_async(&self, queue_url: &str, message: &str) -> anyhow::Result<u32> {
-        if message.len() > 256 * 1024 {
-            return Err(anyhow!("message length can't be above 256kb as per SQS limits"));
+    async fn send_message_async(&self, queue_url: &str, message: &OutgoingMessage) -> anyhow::Result<u32> {
+        if message.size() > MAX_MESSAGE_SIZE {
+            return Err(anyhow!("message length can't be above 256kb as per SQS limits")
+                .context(SQSError::MessageTooLarge));
         }
 
         self.client.send_message()
             .queue_url(queue_url)
-            .message_body(message)
+            .message_body(&message.body)
+            .set_message_attributes(message_attributes(message))
+            .set_message_group_id(message.group_id.clone())
+            .set_message_deduplication_id(message.deduplication_id.clone())
+            .set_delay_seconds(message.delay_seconds)
+            .customize()
+            .interceptor(RetryLogger::new("SendMessage", Some(queue_url)))
             .send()
-            .await?;
+            .await
+            .map_err(classify)?;
 
-        Ok(message.len() as u32)
+        Ok(message.body.len() as u32)
     }
 
-    pub fn send_message(&self, queue_url: &str, message: &str) -> anyhow::Result<u32> {
-        self.send_message_async(queue_url, message)
+    async fn send_message_batch_async(&self, queue_url: &str, messages: &[OutgoingMessage]) -> anyhow::Result<Vec<BatchEntryFailure>> {
+        // entry IDs only need to be unique within the request, the index makes it easy to map
+        // failures back to their message
+        let entries = messages.iter()
+            .enumerate()
+            .map(|(id, message)| SendMessageBatchRequestEntry::builder()
+                .id(id.to_string())
+                .message_body(&message.body)
+                .set_message_attributes(message_attributes(message))
+                .set_message_group_id(message.group_id.clone())
+                .set_message_deduplication_id(message.deduplication_id.clone())
+                .set_delay_seconds(message.delay_seconds)
+                .build())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let send_message_batch_output = self.client.send_message_batch()
+            .queue_url(queue_url)
+            .set_entries(Some(entries))
+            .customize()
+            .interceptor(RetryLogger::new("SendMessageBatch", Some(queue_url)))
+            .send()
+            .await
+            .map_err(classify)?;
+
+        send_message_batch_output.failed().iter()
+            .map(|failure| Ok(BatchEntryFailure {
+                index: failure.id().parse()?,
+                sender_fault: failure.sender_fault(),
+                error: classify_batch_entry(failure),
+            }))
+            .collect()
     }
-    #[tokio::main]
-    async fn receive_message_async(&self, queue_url: &str) -> anyhow::Result<ReceiveMessageOutput> {
+
+    async fn receive_message_async(&self, queue_url: &str, max_messages: i32, wait_time_secs: i32) -> anyhow::Result<Vec<Message>> {
         let receive_message_output = self.client.receive_message()
             .queue_url(queue_url)
-            .max_number_of_messages(1)
+            .max_number_of_messages(max_messages)
+            .wait_time_seconds(wait_time_secs)
+            .message_attribute_names("All")
+            .message_system_attribute_names(MessageSystemAttributeName::All)
+            .customize()
+            .interceptor(RetryLogger::new("ReceiveMessage", Some(queue_url)))
             .send()
-            .await?;
+            .await
+            .map_err(classify)?;
 
-        Ok(receive_message_output)
+        Ok(receive_message_output.messages.unwrap_or_default())
     }
-    pub fn receive_message(&self, queue_url: &str) -> anyhow::Result<ReceiveMessageOutput> {
-        self.receive_message_async(queue_url)
+
+    async fn delete_message_async(&self, queue_url: &str, receipt_handle: &str) -> anyhow::Result<()> {
+        self.client.delete_message()
+            .queue_url(queue_url)
+            .receipt_handle(receipt_handle)
+            .customize()
+            .interceptor(RetryLogger::new("DeleteMessage", Some(queue_url)))
+            .send()
+            .await
+            .map_err(classify)?;
+
+        Ok(())
     }
 
-    #[tokio::main]
-    async fn delete_message_async(&self, queue_url: &str, receipt_handle: &str) -> anyhow::Result<DeleteMessageOutput> {
-        let delete_message_output = self.client.delete_message()
+    async fn delete_message_batch_async(&self, queue_url: &str, receipt_handles: &[String]) -> anyhow::Result<()> {
+        // entry IDs only need to be unique within the request
+        let entries = receipt_handles.iter()
+            .enumerate()
+            .map(|(id, receipt_handle)| DeleteMessageBatchRequestEntry::builder()
+                .id(id.to_string())
+                .receipt_handle(receipt_handle)
+                .build())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let delete_message_batch_output = self.client.delete_message_batch()
+            .queue_url(queue_url)
+            .set_entries(Some(entries))
+            .customize()
+            .interceptor(RetryLogger::new("DeleteMessageBatch", Some(queue_url)))
+            .send()
+            .await
+            .map_err(classify)?;
+
+        // the request itself can succeed while some of its entries fail
+        let failed = delete_message_batch_output.failed();
+        if let Some(failure) = failed.first() {
+            return Err(classify_batch_entry(failure)
+                .context(format!("failed to delete {} out of {} messages", failed.len(), receipt_handles.len())));
+        }
+
+        Ok(())
+    }
+
+    async fn change_message_visibility_async(&self, queue_url: &str, receipt_handle: &str, visibility_timeout: i32) -> anyhow::Result<()> {
+        self.client.change_message_visibility()
             .queue_url(queue_url)
             .receipt_handle(receipt_handle)
+            .visibility_timeout(visibility_timeout)
+            .customize()
+            .interceptor(RetryLogger::new("ChangeMessageVisibility", Some(queue_url)))
             .send()
-            .await?;
+            .await
+            .map_err(classify)?;
+
+        Ok(())
+    }
+}
+
+impl QueueBackend for SQSClient {
+    fn list_queues(&self) -> anyhow::Result<Vec<String>> {
+        self.runtime.block_on(self.list_queues_async())
+    }
+
+    fn create_queue(&self, queue_name: &str) -> anyhow::Result<String> {
+        self.runtime.block_on(self.create_queue_async(queue_name))
+    }
+
+    fn delete_queue(&self, queue_url: &str) -> anyhow::Result<()> {
+        self.runtime.block_on(self.delete_queue_async(queue_url))
+    }
+
+    fn get_queue_attributes(&self, queue_url: &str) -> anyhow::Result<HashMap<QueueAttributeName, String>> {
+        self.runtime.block_on(self.get_queue_attributes_async(queue_url))
+    }
+
+    fn send_message(&self, queue_url: &str, message: &OutgoingMessage) -> anyhow::Result<u32> {
+        self.runtime.block_on(self.send_message_async(queue_url, message))
+    }
 
-        Ok(delete_message_output)
+    fn send_message_batch(&self, queue_url: &str, messages: &[OutgoingMessage]) -> anyhow::Result<Vec<BatchEntryFailure>> {
+        self.runtime.block_on(self.send_message_batch_async(queue_url, messages))
     }
 
-    pub fn delete_message(&self, queue_url: &str, receipt_handle: &str) -> anyhow::Result<DeleteMessageOutput> {
-        self.delete_message_async(queue_url, receipt_handle)
+    fn receive_message(&self, queue_url: &str, max_messages: i32, wait_time_secs: i32) -> anyhow::Result<Vec<Message>> {
+        self.runtime.block_on(self.receive_message_async(queue_url, max_messages, wait_time_secs))
     }
+
+    fn delete_message(&self, queue_url: &str, receipt_handle: &str) -> anyhow::Result<()> {
+        self.runtime.block_on(self.delete_message_async(queue_url, receipt_handle))
+    }
+
+    fn delete_message_batch(&self, queue_url: &str, receipt_handles: &[String]) -> anyhow::Result<()> {
+        self.runtime.block_on(self.delete_message_batch_async(queue_url, receipt_handles))
+    }
+
+    fn change_message_visibility(&self, queue_url: &str, receipt_handle: &str, visibility_timeout: i32) -> anyhow::Result<()> {
+        self.runtime.block_on(self.change_message_visibility_async(queue_url, receipt_handle, visibility_timeout))
+    }
+}
+
+/// SQS requires the name of FIFO queues to end with .fifo
+pub fn is_fifo_queue(queue_name: &str) -> bool {
+    queue_name.ends_with(".fifo")
 }
 
 pub fn get_queue_name(queue_url: &str) -> anyhow::Result<String> {
     let url = Url::parse(queue_url)?;
 
-    let segments = url.path_segments();
+    let mut segments = url.path_segments()
+        .ok_or_else(|| anyhow!("No segments: {}", queue_url))?;
+
+    // Endpoints other than SQS itself (ElasticMQ, LocalStack, ...) shape their queue urls
+    // differently, e.g. http://localhost:9324/000000000000/q or http://localhost:9324/queue/q/.
+    // The queue name is always the last non-empty segment though.
+    let last = segments.rfind(|segment| !segment.is_empty());
 
-    if segments.is_none() {
-        return Err(anyhow!("No segments: {}", queue_url));
+    match last {
+        Some(queue_name) => Ok(queue_name.to_string()),
+        None => Err(anyhow!("No queue name: {}", queue_url)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_sqs::config::http::HttpResponse;
+    use aws_sdk_sqs::error::ConnectorError;
+    use aws_sdk_sqs::operation::list_queues::ListQueuesError;
+
+    use super::*;
+
+    fn dispatch_failure(error: ConnectorError) -> SdkError<ListQueuesError, HttpResponse> {
+        SdkError::dispatch_failure(error)
     }
 
-    let last = segments.unwrap().last();
+    #[test]
+    fn credentials_that_cant_be_loaded_are_access_denied() {
+        let error = dispatch_failure(ConnectorError::other(CredentialsError::not_loaded("no credentials").into(), None));
+
+        assert_eq!(to_errno(&classify(error)), libc::EACCES);
+    }
+
+    #[test]
+    fn dispatch_failures_are_timeouts_or_network_errors() {
+        assert_eq!(to_errno(&classify(dispatch_failure(ConnectorError::timeout("timed out".into())))), libc::ETIMEDOUT);
+        assert_eq!(to_errno(&classify(dispatch_failure(ConnectorError::io("connection refused".into())))), libc::EIO);
+    }
 
-    if last.is_none() {
-        return Err(anyhow!("No queue name: {}", queue_url));
+    #[test]
+    fn unclassified_errors_are_io_errors() {
+        assert_eq!(to_errno(&anyhow!("boom")), libc::EIO);
+        assert_eq!(to_errno(&anyhow!("boom").context(SQSError::AccessDenied).context("while listing")), libc::EACCES);
     }
 
-    Ok(last.unwrap().to_string())
+    #[test]
+    fn error_codes_are_classified() {
+        let errno = |code: &str, message: &str| classify_code(Some(code), Some(message)).map(|error| error.errno());
+
+        assert_eq!(errno("AccessDenied", ""), Some(libc::EACCES));
+        assert_eq!(errno("AWS.SimpleQueueService.NonExistentQueue", ""), Some(libc::ENOENT));
+        assert_eq!(errno("ThrottlingException", ""), Some(libc::EAGAIN));
+        assert_eq!(errno("AWS.SimpleQueueService.BatchRequestTooLong", ""), Some(libc::EFBIG));
+        assert_eq!(errno("InvalidParameterValue", "One or more parameters are invalid. Reason: Message must be shorter than 262144 bytes."), Some(libc::EFBIG));
+        assert_eq!(errno("InvalidParameterValue", "Value for parameter MaxNumberOfMessages is invalid."), Some(libc::EINVAL));
+        assert_eq!(errno("InternalError", ""), None);
+        assert_eq!(classify_code(None, None).map(|error| error.errno()), None);
+    }
+
+    #[test]
+    fn queue_names_come_from_the_last_segment_of_the_url() {
+        let urls = [
+            "https://sqs.us-east-1.amazonaws.com/123456789012/jobs",
+            // ElasticMQ
+            "http://localhost:9324/000000000000/jobs",
+            "http://localhost:9324/queue/jobs",
+            "http://localhost:9324/queue/jobs/",
+            // LocalStack
+            "http://localhost:4566/000000000000/jobs",
+            "http://sqs.us-east-1.localhost.localstack.cloud:4566/000000000000/jobs/",
+        ];
+
+        for url in urls {
+            assert_eq!(get_queue_name(url).unwrap(), "jobs", "{}", url);
+        }
+    }
+
+    #[test]
+    fn urls_without_a_queue_name_are_refused() {
+        assert!(get_queue_name("http://localhost:9324/").is_err());
+        assert!(get_queue_name("not a url").is_err());
+    }
 }